@@ -46,30 +46,15 @@ echo "CONTRACT_ID:" $CONTRACT_ID
 
 echo "Initializing contract..."
 
-# soroban contract invoke \
-#     --id $CONTRACT_ID \
-#     --source $ADMIN_ID_STR \
-#     --network Futurenet \
-#     -- init \
-#     --admin $ADMIN_ACC
-
 soroban contract invoke \
     --id $CONTRACT_ID \
     --source $ADMIN_ID_STR \
     --network Futurenet \
-    -- set_fee \
-    --fee_rate 25 \
-    --fee_wallet $FEE_ACC
-
-soroban contract invoke \
-	--id $CONTRACT_ID \
-	--network Futurenet \
-	-- allow_token \
-	--token $(cat ../.soroban/ngnc-id)
-soroban contract invoke \
-	--id $(cat ../.soroban/contract-id) \
-	--network Futurenet \
-	-- allow_token \
-	--token $(cat ../.soroban/usdc-id)
+    -- initialize \
+    --admin $ADMIN_ACC \
+    --maker_fee_rate 25 \
+    --taker_fee_rate 25 \
+    --fee_wallet $FEE_ACC \
+    --allowed_tokens "[\"$(cat ../.soroban/ngnc-id)\", \"$(cat ../.soroban/usdc-id)\"]"
 
 echo "Contract initialized."
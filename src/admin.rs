@@ -1,18 +1,100 @@
-use soroban_sdk::{Address, Env};
+const ADMIN: Symbol = symbol_short!("ADMIN");
 
-use crate::storage_types::DataKey;
+use soroban_sdk::{symbol_short, Address, Env, Symbol};
+
+use crate::storage_types::{ CONTRACT_VERSION, INSTANCE_BUMP_AMOUNT, INSTANCE_LIFETIME_THRESHOLD, DataKey };
+use crate::error::SwapError;
+
+use crate::role::{
+    role_transfer,
+};
+use crate::upgrade::{
+    version_get,
+};
 
 pub fn has_administrator(e: &Env) -> bool {
     let key = DataKey::Admin;
     e.storage().instance().has(&key)
 }
 
-pub fn read_administrator(e: &Env) -> Address {
+// Fails until the contract has been initialized, and after an upgrade until it has been migrated.
+pub fn require_initialized(e: &Env) -> Result<(), SwapError> {
+    if !has_administrator(e) {
+        return Err(SwapError::NotInitialized);
+    }
+    if version_get(e) < CONTRACT_VERSION {
+        return Err(SwapError::NotMigrated);
+    }
+    Ok(())
+}
+
+pub fn read_administrator(e: &Env) -> Result<Address, SwapError> {
     let key = DataKey::Admin;
-    e.storage().instance().get(&key).unwrap()
+    e.storage().instance().get(&key).ok_or(SwapError::NotInitialized)
 }
 
 pub fn write_administrator(e: &Env, id: &Address) {
     let key = DataKey::Admin;
     e.storage().instance().set(&key, id);
 }
+
+pub fn read_pending_administrator(e: &Env) -> Option<Address> {
+    let key = DataKey::PendingAdmin;
+    e.storage().instance().get(&key)
+}
+
+// Proposes the new admin, who has to accept it with `admin_accept`.
+// Must be authorized by the current admin.
+pub fn admin_propose(e: &Env, new_admin: &Address) -> Result<(), SwapError> {
+    let admin = read_administrator(e)?;
+    admin.require_auth();
+
+    e.storage().instance().set(&DataKey::PendingAdmin, new_admin);
+
+    // emit AdminProposed event
+    e.events().publish((ADMIN, symbol_short!("APropose")), 
+        (admin, new_admin.clone())
+    );
+
+    Ok(())
+}
+
+// Makes the pending admin the admin, the roles of the previous admin move along.
+// Must be authorized by the pending admin.
+pub fn admin_accept(e: &Env) -> Result<(), SwapError> {
+    let admin = read_administrator(e)?;
+    let new_admin = read_pending_administrator(e).ok_or(SwapError::NoPendingAdmin)?;
+    new_admin.require_auth();
+
+    e.storage()
+        .instance()
+        .extend_ttl(INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT);
+
+    write_administrator(e, &new_admin);
+    e.storage().instance().remove(&DataKey::PendingAdmin);
+    role_transfer(e, &admin, &new_admin);
+
+    // emit AdminAccepted event
+    e.events().publish((ADMIN, symbol_short!("AAccept")), 
+        (admin, new_admin)
+    );
+
+    Ok(())
+}
+
+// Drops the pending admin.
+// Must be authorized by the current admin.
+pub fn admin_cancel(e: &Env) -> Result<(), SwapError> {
+    let admin = read_administrator(e)?;
+    admin.require_auth();
+
+    let new_admin = read_pending_administrator(e).ok_or(SwapError::NoPendingAdmin)?;
+    e.storage().instance().remove(&DataKey::PendingAdmin);
+
+    // emit AdminCancelled event
+    e.events().publish((ADMIN, symbol_short!("ACancel")), 
+        (admin, new_admin)
+    );
+
+    Ok(())
+}
@@ -1,46 +1,46 @@
 use soroban_sdk::{ log, Address, Env };
 
 use crate::storage_types::{ /* INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT, */ 
-    DataKey
+    DataKey, Role
 };
+use crate::error::SwapError;
 
-use crate::admin::{ 
-    read_administrator, 
+use crate::role::{ 
+    require_role, 
 };
 
 
-pub fn allow_set(e: &Env, token_addr: &Address) {
-    let key = DataKey::Allowance(token_addr.clone());
-    let admin = read_administrator(&e);
-    admin.require_auth();
-    if e.storage().instance().has(&key) && e.storage().instance().get::<_, bool>(&key).unwrap() {
-        log!(&e, "current token was already allowed");
-        return;
+pub fn allow_set(e: &Env, caller: &Address, token_addr: &Address) -> Result<(), SwapError> {
+    require_role(e, Role::TokenLister, caller)?;
+    if allow_get(e, token_addr) {
+        log!(e, "current token was already allowed");
+        return Err(SwapError::TokenAlreadyAllowed);
     }
 
+    allow_write(e, token_addr);
+    Ok(())
+}
+
+pub fn allow_write(e: &Env, token_addr: &Address) {
+    let key = DataKey::Allowance(token_addr.clone());
     e.storage().instance().set(&key, &true);
 }
 
-pub fn allow_reset(e: &Env, token_addr: &Address) {
+pub fn allow_reset(e: &Env, caller: &Address, token_addr: &Address) -> Result<(), SwapError> {
     let key = DataKey::Allowance(token_addr.clone());
-    let admin = read_administrator(&e);
-    admin.require_auth();
-    if !e.storage().instance().has(&key) || !e.storage().instance().get::<_, bool>(&key).unwrap() {
-        log!(&e, "current token wasn't allowed");
-        return;
+    require_role(e, Role::TokenLister, caller)?;
+    if !allow_get(e, token_addr) {
+        log!(e, "current token wasn't allowed");
+        return Err(SwapError::TokenNotAllowed);
     }
 
     e.storage().instance().set(&key, &false);
     // e.storage().instance().bump(INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT);
+    Ok(())
 }
 
 pub fn allow_get(e: &Env, token: &Address) -> bool {
     let key = DataKey::Allowance(token.clone());
-    
-    if e.storage().instance().has(&key) && e.storage().instance().get::<_, bool>(&key).unwrap() {
-        true
-    }
-    else {
-        false
-    }
+
+    e.storage().instance().get::<_, bool>(&key).unwrap_or(false)
 }
@@ -0,0 +1,56 @@
+use soroban_sdk::contracterror;
+
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum SwapError {
+    // admin
+    AlreadyInitialized = 1,
+    NotInitialized = 2,
+    NoPendingAdmin = 3,
+    MissingRole = 4,
+    AlreadyMigrated = 5,
+    NotMigrated = 6,
+
+    // fee
+    FeeNotSet = 10,
+    InvalidFeeRate = 11,
+    InvalidFeeCap = 12,
+    PairFeeNotSet = 13,
+    InvalidFeeTiers = 14,
+    InsufficientFees = 15,
+    InvalidFeeRecipients = 16,
+    InvalidReferralFee = 17,
+    InvalidFeeToken = 18,
+    InvalidFeeSchedule = 19,
+
+    // allow
+    TokenNotAllowed = 20,
+    TokenAlreadyAllowed = 21,
+
+    // pause
+    ContractPaused = 25,
+    TokenPaused = 26,
+
+    // offer
+    OfferNotFound = 30,
+    OfferNotActive = 31,
+    InvalidOfferor = 32,
+    ZeroAmount = 33,
+    InvalidMinRecvAmount = 34,
+    InsufficientBalance = 35,
+    AmountAboveRecvAmount = 36,
+    AmountBelowMinRecvAmount = 37,
+    OfferExpired = 38,
+    OfferNotExpired = 39,
+    InvalidExpiry = 40,
+    SendAmountBelowMin = 41,
+    DeadlinePassed = 42,
+    AmountAboveSendAmount = 43,
+    InvalidAcceptor = 44,
+    TooManyAcceptors = 45,
+    InvalidFillPolicy = 46,
+    FillNotAllowed = 47,
+    AmountOverflow = 48,
+}
@@ -1,41 +1,480 @@
-use soroban_sdk::{ Env };
-use crate::storage_types::{ FEE_DECIMALS, /* INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT, */ 
-    DataKey, FeeInfo
+const FEE: Symbol = symbol_short!("FEE");
+
+use soroban_sdk::{ symbol_short, token, Address, Env, Symbol, Vec };
+use crate::storage_types::{ FEE_DECIMALS, MAX_FEE_RATE, SHARE_BPS_TOTAL, MAX_FEE_RECIPIENTS, MIN_FEE_DELAY, MAX_PENDING_FEE_CHANGES, MAX_PAGE_SIZE, INDEX_PAGE_SIZE, 
+    BALANCE_BUMP_AMOUNT, BALANCE_LIFETIME_THRESHOLD, /* INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT, */ 
+    DataKey, FeeChange, FeeInfo, FeeRecipient, PairFee, PendingFee, Referral, Role
 };
+use crate::error::SwapError;
 
 use crate::admin::{ 
-    read_administrator, 
+    read_administrator, require_initialized, 
+};
+use crate::role::{ 
+    require_role, 
+};
+use crate::tier::{ 
+    tiers_write, 
+};
+use crate::fee_token::{ 
+    fee_token_write, fee_token_price_write, 
 };
 
-pub fn fee_check(e: &Env) -> bool {
+// Fee in effect at the current ledger, a pending change counts once its ledger is reached.
+pub fn fee_get(e: &Env) -> Result<FeeInfo, SwapError> {
+    if let Some(FeeChange::Fee(fee_info)) = fee_change_due(e, &FeeChange::Fee(FeeInfo { maker_fee_rate: 0, taker_fee_rate: 0 })) {
+        return Ok(fee_info);
+    }
+
     let key = DataKey::FEE;
+    e.storage().instance().get(&key).ok_or(SwapError::FeeNotSet)
+}
+
+// Pending fee change that hasn't been applied yet, it may already be in effect.
+pub fn fee_pending_get(e: &Env) -> Option<PendingFee> {
+    fee_changes_get(e).iter().find_map(|(effective_ledger, change)| match change {
+        FeeChange::Fee(fee_info) => Some(PendingFee { fee_info, effective_ledger }),
+        _ => None,
+    })
+}
+
+// Schedules a fee change from `effective_ledger` on.
+// Must be authorized by a fee manager.
+pub fn fee_set(e: &Env, caller: &Address, fee_info: &FeeInfo, effective_ledger: u32) -> Result<(), SwapError> {
+    require_role(e, Role::FeeManager, caller)?;
+    fee_check(e, fee_info)?;
+
+    fee_change_schedule(e, &FeeChange::Fee(fee_info.clone()), effective_ledger)
+}
 
-    if e.storage().instance().has(&key) {
-        true
+// Fee setting changes that haven't been applied yet with the ledger they take effect from.
+pub fn fee_changes_get(e: &Env) -> Vec<(u32, FeeChange)> {
+    e.storage().persistent().get(&DataKey::PendingFeeChanges).unwrap_or(Vec::new(e))
+}
+
+fn fee_changes_write(e: &Env, changes: &Vec<(u32, FeeChange)>) {
+    let key = DataKey::PendingFeeChanges;
+    if changes.is_empty() {
+        e.storage().persistent().remove(&key);
     }
     else {
-        false
+        e.storage().persistent().set(&key, changes);
+        e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
     }
 }
 
-pub fn fee_get(e: &Env) -> FeeInfo {
-    let key = DataKey::FEE;
+// Pending change of the same setting as `change` already in effect, if any.
+pub fn fee_change_due(e: &Env, change: &FeeChange) -> Option<FeeChange> {
+    let sequence = e.ledger().sequence();
+    fee_changes_get(e).iter()
+        .find(|(effective_ledger, pending)| *effective_ledger <= sequence && fee_change_same(pending, change))
+        .map(|(_, pending)| pending)
+}
 
-    if !e.storage().instance().has(&key) {
-        panic!("FeeInfo wasn't initialized");
+// Schedules a fee setting change from `effective_ledger` on, at least MIN_FEE_DELAY ledgers ahead.
+// Replaces any change of the same setting that isn't in effect yet,
+// at most MAX_PENDING_FEE_CHANGES changes of each kind of setting are pending.
+pub fn fee_change_schedule(e: &Env, change: &FeeChange, effective_ledger: u32) -> Result<(), SwapError> {
+    require_initialized(e)?;
+    if effective_ledger < e.ledger().sequence().saturating_add(MIN_FEE_DELAY) {
+        return Err(SwapError::InvalidFeeSchedule);
     }
-    
-    e.storage().instance().get(&key).unwrap()
+
+    fee_changes_settle(e);
+    let mut changes = fee_changes_get(e);
+    if let Some(pos) = changes.iter().position(|(_, pending)| fee_change_same(&pending, change)) {
+        changes.remove(pos as u32);
+    }
+    if changes.iter().filter(|(_, pending)| fee_change_kind(pending) == fee_change_kind(change)).count() as u32 >= MAX_PENDING_FEE_CHANGES {
+        return Err(SwapError::InvalidFeeSchedule);
+    }
+    changes.push_back((effective_ledger, change.clone()));
+    fee_changes_write(e, &changes);
+
+    // emit FeeScheduled event
+    e.events().publish((FEE, symbol_short!("FSchedule")), 
+        (change.clone(), effective_ledger)
+    );
+
+    Ok(())
 }
 
-pub fn fee_set(e: &Env, fee_info: &FeeInfo) {
-    let key = DataKey::FEE;
-    let admin = read_administrator(&e);
+// Applies the pending changes in effect and records them in the fee history.
+fn fee_changes_settle(e: &Env) {
+    for (effective_ledger, change) in fee_changes_due(e).iter() {
+        match &change {
+            FeeChange::Fee(fee_info) => fee_write(e, fee_info),
+            FeeChange::PairFee(token_a, token_b, pair_fee) => pair_fee_write(e, token_a, token_b, Some(pair_fee)),
+            FeeChange::PairFeeClear(token_a, token_b) => pair_fee_write(e, token_a, token_b, None),
+            FeeChange::Tiers(token, tiers) => tiers_write(e, token, tiers),
+            FeeChange::FeeToken(fee_token) => fee_token_write(e, &Some(fee_token.clone())),
+            FeeChange::FeeTokenClear => fee_token_write(e, &None),
+            FeeChange::FeeTokenPrice(token, price) => fee_token_price_write(e, token, *price),
+        }
+        fee_history_add(e, effective_ledger, &change);
+    }
+
+    let sequence = e.ledger().sequence();
+    let mut changes = Vec::new(e);
+    for (effective_ledger, change) in fee_changes_get(e).iter() {
+        if effective_ledger > sequence {
+            changes.push_back((effective_ledger, change));
+        }
+    }
+    fee_changes_write(e, &changes);
+}
+
+// Pending changes in effect in the order they took effect, by ledger then by scheduling order.
+fn fee_changes_due(e: &Env) -> Vec<(u32, FeeChange)> {
+    let sequence = e.ledger().sequence();
+    let mut due: Vec<(u32, FeeChange)> = Vec::new(e);
+    for (effective_ledger, change) in fee_changes_get(e).iter() {
+        if effective_ledger > sequence {
+            continue;
+        }
+        let pos = due.iter().position(|(ledger, _)| ledger > effective_ledger).map_or(due.len(), |pos| pos as u32);
+        due.insert(pos, (effective_ledger, change));
+    }
+    due
+}
+
+// Kind of setting the change is of, pair fee overrides and their removals are of the same kind.
+fn fee_change_kind(change: &FeeChange) -> u32 {
+    match change {
+        FeeChange::Fee(_) => 0,
+        FeeChange::PairFee(..) | FeeChange::PairFeeClear(..) => 1,
+        FeeChange::Tiers(..) => 2,
+        FeeChange::FeeToken(_) | FeeChange::FeeTokenClear => 3,
+        FeeChange::FeeTokenPrice(..) => 4,
+    }
+}
+
+// Whether both changes are of the same setting.
+fn fee_change_same(a: &FeeChange, b: &FeeChange) -> bool {
+    match (a, b) {
+        (FeeChange::Fee(_), FeeChange::Fee(_)) => true,
+        (FeeChange::PairFee(a1, a2, _) | FeeChange::PairFeeClear(a1, a2), 
+            FeeChange::PairFee(b1, b2, _) | FeeChange::PairFeeClear(b1, b2)) => a1 == b1 && a2 == b2,
+        (FeeChange::Tiers(a_token, _), FeeChange::Tiers(b_token, _)) => a_token == b_token,
+        (FeeChange::FeeToken(_) | FeeChange::FeeTokenClear, FeeChange::FeeToken(_) | FeeChange::FeeTokenClear) => true,
+        (FeeChange::FeeTokenPrice(a_token, _), FeeChange::FeeTokenPrice(b_token, _)) => a_token == b_token,
+        _ => false,
+    }
+}
+
+// Every fee setting change in the order it took effect, with its ledger, in pages of INDEX_PAGE_SIZE.
+
+pub fn fee_history_len(e: &Env) -> u32 {
+    e.storage().persistent().get(&DataKey::FeeHistoryLen).unwrap_or(0)
+}
+
+fn fee_history_page(e: &Env, page: u32) -> Vec<(u32, FeeChange)> {
+    e.storage().persistent().get(&DataKey::FeeHistory(page)).unwrap_or(Vec::new(e))
+}
+
+pub fn fee_history_add(e: &Env, ledger: u32, change: &FeeChange) {
+    let pos = fee_history_len(e);
+    let mut history = fee_history_page(e, pos / INDEX_PAGE_SIZE);
+    history.push_back((ledger, change.clone()));
+    let key = DataKey::FeeHistory(pos / INDEX_PAGE_SIZE);
+    e.storage().persistent().set(&key, &history);
+    e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+
+    let key = DataKey::FeeHistoryLen;
+    e.storage().persistent().set(&key, &(pos + 1));
+    e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+}
+
+// Up to MAX_PAGE_SIZE fee setting changes from position `start` on,
+// and the position to continue from if there are more.
+pub fn fee_history_get(e: &Env, start: u32, limit: u32) -> (Vec<(u32, FeeChange)>, Option<u32>) {
+    let recorded = fee_history_len(e);
+    // pending changes in effect are history even if they weren't applied yet,
+    // they come last in the positions they'll be recorded at
+    let due = fee_changes_due(e);
+    let len = recorded + due.len();
+    let end = len.min(start.saturating_add(limit.min(MAX_PAGE_SIZE)));
+    let mut history = Vec::new(e);
+
+    let mut page = Vec::new(e);
+    for pos in start..end {
+        if pos >= recorded {
+            history.push_back(due.get_unchecked(pos - recorded));
+            continue;
+        }
+        if pos == start || pos % INDEX_PAGE_SIZE == 0 {
+            page = fee_history_page(e, pos / INDEX_PAGE_SIZE);
+        }
+        history.push_back(page.get_unchecked(pos % INDEX_PAGE_SIZE));
+    }
+
+    (history, (start < end && end < len).then_some(end))
+}
+
+// Fails if the fee rate is above the fee cap.
+pub fn fee_check(e: &Env, fee_info: &FeeInfo) -> Result<(), SwapError> {
+    let fee_cap = fee_cap_get(e);
+    if fee_info.maker_fee_rate > fee_cap || fee_info.taker_fee_rate > fee_cap {
+        return Err(SwapError::InvalidFeeRate);
+    }
+    Ok(())
+}
+
+pub fn fee_cap_get(e: &Env) -> u32 {
+    e.storage().instance().get(&DataKey::FeeCap).unwrap_or(MAX_FEE_RATE)
+}
+
+// Lowers the fee cap, it can never be raised again.
+// Must be authorized by admin.
+pub fn fee_cap_set(e: &Env, fee_cap: u32) -> Result<(), SwapError> {
+    let admin = read_administrator(e)?;
     admin.require_auth();
-    e.storage().instance().set(&key, fee_info);
-    // e.storage().instance().bump(INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT);
+
+    if fee_cap > fee_cap_get(e) {
+        return Err(SwapError::InvalidFeeCap);
+    }
+    let fee_info = fee_get(e)?;
+    if fee_cap < fee_info.maker_fee_rate || fee_cap < fee_info.taker_fee_rate {
+        return Err(SwapError::InvalidFeeRate);
+    }
+    if let Some(pending_fee) = fee_pending_get(e) {
+        if fee_cap < pending_fee.fee_info.maker_fee_rate || fee_cap < pending_fee.fee_info.taker_fee_rate {
+            return Err(SwapError::InvalidFeeRate);
+        }
+    }
+
+    e.storage().instance().set(&DataKey::FeeCap, &fee_cap);
+    Ok(())
+}
+
+pub fn fee_write(e: &Env, fee_info: &FeeInfo) {
+    e.storage().instance().set(&DataKey::FEE, fee_info);
+}
+
+pub fn pair_fee_get(e: &Env, send_token: &Address, recv_token: &Address) -> Option<PairFee> {
+    let (token_a, token_b) = pair_fee_tokens(send_token, recv_token);
+    match fee_change_due(e, &FeeChange::PairFeeClear(token_a.clone(), token_b.clone())) {
+        Some(FeeChange::PairFee(_, _, pair_fee)) => Some(pair_fee),
+        Some(_) => None,
+        None => e.storage().persistent().get(&DataKey::PairFee(token_a, token_b)),
+    }
+}
+
+fn pair_fee_write(e: &Env, token_a: &Address, token_b: &Address, pair_fee: Option<&PairFee>) {
+    let key = DataKey::PairFee(token_a.clone(), token_b.clone());
+    match pair_fee {
+        Some(pair_fee) => {
+            e.storage().persistent().set(&key, pair_fee);
+            e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+        },
+        None => e.storage().persistent().remove(&key),
+    }
+}
+
+// Schedules a pair fee override from `effective_ledger` on.
+// Must be authorized by a fee manager.
+pub fn pair_fee_set(e: &Env, 
+    caller: &Address, 
+    send_token: &Address, 
+    recv_token: &Address, 
+    pair_fee: &PairFee, 
+    effective_ledger: u32
+) -> Result<(), SwapError> {
+    require_role(e, Role::FeeManager, caller)?;
+    let fee_cap = fee_cap_get(e);
+    if pair_fee.maker_fee_rate > fee_cap || pair_fee.taker_fee_rate > fee_cap {
+        return Err(SwapError::InvalidFeeRate);
+    }
+
+    let (token_a, token_b) = pair_fee_tokens(send_token, recv_token);
+    fee_change_schedule(e, &FeeChange::PairFee(token_a, token_b, pair_fee.clone()), effective_ledger)
+}
+
+// Schedules the removal of a pair fee override from `effective_ledger` on.
+// Must be authorized by a fee manager.
+pub fn pair_fee_clear(e: &Env, 
+    caller: &Address, 
+    send_token: &Address, 
+    recv_token: &Address, 
+    effective_ledger: u32
+) -> Result<(), SwapError> {
+    require_role(e, Role::FeeManager, caller)?;
+    let (token_a, token_b) = pair_fee_tokens(send_token, recv_token);
+    // an override not in effect yet may be cancelled too
+    let pending = fee_changes_get(e).iter()
+        .any(|(_, change)| matches!(change, FeeChange::PairFee(a, b, _) if a == token_a && b == token_b));
+    if pair_fee_get(e, send_token, recv_token).is_none() && !pending {
+        return Err(SwapError::PairFeeNotSet);
+    }
+
+    fee_change_schedule(e, &FeeChange::PairFeeClear(token_a, token_b), effective_ledger)
+}
+
+fn pair_fee_tokens(send_token: &Address, recv_token: &Address) -> (Address, Address) {
+    if send_token < recv_token {
+        (send_token.clone(), recv_token.clone())
+    }
+    else {
+        (recv_token.clone(), send_token.clone())
+    }
+}
+
+// (maker, taker) fee rates of the token pair, the pair override if any or the global rates.
+pub fn fee_rates(e: &Env, fee_info: &FeeInfo, send_token: &Address, recv_token: &Address) -> (u32, u32) {
+    match pair_fee_get(e, send_token, recv_token) {
+        // the fee cap may have been lowered since the override was set
+        Some(pair_fee) => {
+            let fee_cap = fee_cap_get(e);
+            (pair_fee.maker_fee_rate.min(fee_cap), pair_fee.taker_fee_rate.min(fee_cap))
+        },
+        None => (fee_info.maker_fee_rate, fee_info.taker_fee_rate),
+    }
+}
+
+pub fn fee_accrued_get(e: &Env, token: &Address) -> u128 {
+    let key = DataKey::AccruedFees(token.clone());
+    e.storage().persistent().get(&key).unwrap_or(0)
+}
+
+// Books a fee already transferred to the contract.
+pub fn fee_accrue(e: &Env, token: &Address, fee_amount: u64) {
+    if fee_amount == 0 {
+        return;
+    }
+
+    let accrued = fee_accrued_get(e, token) + fee_amount as u128;
+    fee_accrued_write(e, token, accrued);
+}
+
+fn fee_accrued_write(e: &Env, token: &Address, accrued: u128) {
+    let key = DataKey::AccruedFees(token.clone());
+    e.storage().persistent().set(&key, &accrued);
+    e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+}
+
+// Pays accrued fees of the token out to the fee recipients, split by their shares.
+// The rounding remainder goes to the last recipient.
+// Must be authorized by a fee manager.
+pub fn fee_withdraw(e: &Env, caller: &Address, token: &Address, amount: u128) -> Result<(), SwapError> {
+    require_role(e, Role::FeeManager, caller)?;
+    require_initialized(e)?;
+
+    let accrued = fee_accrued_get(e, token);
+    if amount == 0 || amount > accrued {
+        return Err(SwapError::InsufficientFees);
+    }
+
+    let contract = e.current_contract_address();
+    let token_client = token::Client::new(e, token);
+    let recipients = fee_recipients_get(e);
+    let mut remaining = amount;
+    for (i, recipient) in recipients.iter().enumerate() {
+        let share = if i as u32 == recipients.len() - 1 {
+            remaining
+        }
+        else {
+            amount * (recipient.share_bps as u128) / (SHARE_BPS_TOTAL as u128)
+        };
+        if share > 0 {
+            token_client.transfer(&contract, &recipient.wallet, &(share as i128));
+        }
+        remaining -= share;
+    }
+    fee_accrued_write(e, token, accrued - amount);
+
+    // emit FeeWithdrawn event
+    e.events().publish((FEE, symbol_short!("FWithdraw")), 
+        (token.clone(), amount)
+    );
+
+    Ok(())
+}
+
+pub fn fee_recipients_get(e: &Env) -> Vec<FeeRecipient> {
+    e.storage().instance().get(&DataKey::FeeRecipients).unwrap_or(Vec::new(e))
+}
+
+// Fails unless the shares are positive and sum up to SHARE_BPS_TOTAL.
+pub fn fee_recipients_check(recipients: &Vec<FeeRecipient>) -> Result<(), SwapError> {
+    if recipients.is_empty() || recipients.len() > MAX_FEE_RECIPIENTS {
+        return Err(SwapError::InvalidFeeRecipients);
+    }
+
+    let mut total_bps: u32 = 0;
+    for recipient in recipients.iter() {
+        if recipient.share_bps == 0 || recipient.share_bps > SHARE_BPS_TOTAL {
+            return Err(SwapError::InvalidFeeRecipients);
+        }
+        total_bps += recipient.share_bps;
+    }
+    if total_bps != SHARE_BPS_TOTAL {
+        return Err(SwapError::InvalidFeeRecipients);
+    }
+    Ok(())
+}
+
+pub fn fee_recipients_write(e: &Env, recipients: &Vec<FeeRecipient>) {
+    e.storage().instance().set(&DataKey::FeeRecipients, recipients);
+}
+
+// Must be authorized by a fee manager.
+pub fn fee_recipients_set(e: &Env, caller: &Address, recipients: &Vec<FeeRecipient>) -> Result<(), SwapError> {
+    require_role(e, Role::FeeManager, caller)?;
+    fee_recipients_check(recipients)?;
+    fee_recipients_write(e, recipients);
+    Ok(())
+}
+
+pub fn referral_cap_get(e: &Env) -> u32 {
+    e.storage().instance().get(&DataKey::ReferralFeeCap).unwrap_or(0)
+}
+
+// Sets the highest share of the fee, in basis points, that a referrer may take.
+// Must be authorized by admin.
+pub fn referral_cap_set(e: &Env, referral_cap: u32) -> Result<(), SwapError> {
+    let admin = read_administrator(e)?;
+    admin.require_auth();
+
+    if referral_cap > SHARE_BPS_TOTAL {
+        return Err(SwapError::InvalidReferralFee);
+    }
+    e.storage().instance().set(&DataKey::ReferralFeeCap, &referral_cap);
+    Ok(())
+}
+
+// Fails if the referrer asks for more than the referral cap.
+pub fn referral_check(e: &Env, referral: &Referral) -> Result<(), SwapError> {
+    if let Referral::Referrer(_, referrer_fee_bps) = referral {
+        if *referrer_fee_bps > referral_cap_get(e) {
+            return Err(SwapError::InvalidReferralFee);
+        }
+    }
+    Ok(())
+}
+
+// Books a fee already transferred to the contract, paying the referrer's share out first.
+pub fn fee_collect(e: &Env, 
+    offer_id: u32, 
+    token: &Address, 
+    fee_amount: u64, 
+    referral: &Referral
+) {
+    let mut referral_amount: u64 = 0;
+    if let Referral::Referrer(referrer, referrer_fee_bps) = referral {
+        referral_amount = ((fee_amount as u128) * (*referrer_fee_bps as u128) / (SHARE_BPS_TOTAL as u128)) as u64;
+        if referral_amount > 0 {
+            token::Client::new(e, token).transfer(&e.current_contract_address(), referrer, &(referral_amount as i128));
+
+            // emit ReferralPaid event
+            e.events().publish((FEE, symbol_short!("Referral")), 
+                (offer_id, referrer.clone(), token.clone(), referral_amount)
+            );
+        }
+    }
+    fee_accrue(e, token, fee_amount - referral_amount);
 }
 
-pub fn calculate_fee(_e: &Env, fee_info: &FeeInfo, amount: u64) -> u64 {
-    amount * (fee_info.fee_rate as u64) / (u64::pow(10, FEE_DECIMALS))
+pub fn calculate_fee(_e: &Env, fee_rate: u32, amount: u64) -> u64 {
+    // fee_rate is capped below 100%, so the fee always fits in u64
+    ((amount as u128) * (fee_rate as u128) / (u128::pow(10, FEE_DECIMALS))) as u64
 }
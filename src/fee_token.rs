@@ -0,0 +1,122 @@
+use soroban_sdk::{ Address, Env };
+
+use crate::storage_types::{ BALANCE_BUMP_AMOUNT, BALANCE_LIFETIME_THRESHOLD, PRICE_DECIMALS, SHARE_BPS_TOTAL, 
+    DataKey, FeeChange, FeeToken, Role
+};
+use crate::error::SwapError;
+
+use crate::fee::{
+    fee_change_due, fee_change_schedule,
+};
+use crate::role::{
+    require_role,
+};
+
+
+pub fn fee_token_get(e: &Env) -> Option<FeeToken> {
+    match fee_change_due(e, &FeeChange::FeeTokenClear) {
+        Some(FeeChange::FeeToken(fee_token)) => Some(fee_token),
+        Some(_) => None,
+        None => e.storage().instance().get(&DataKey::FeeToken),
+    }
+}
+
+// Schedules the fee token and its discount from `effective_ledger` on, `None` turns the mode off.
+// Must be authorized by a fee manager.
+pub fn fee_token_set(e: &Env, caller: &Address, fee_token: &Option<FeeToken>, effective_ledger: u32) -> Result<(), SwapError> {
+    require_role(e, Role::FeeManager, caller)?;
+
+    let change = match fee_token {
+        Some(fee_token) => {
+            if fee_token.discount_bps > SHARE_BPS_TOTAL {
+                return Err(SwapError::InvalidFeeToken);
+            }
+            FeeChange::FeeToken(fee_token.clone())
+        },
+        None => FeeChange::FeeTokenClear,
+    };
+    fee_change_schedule(e, &change, effective_ledger)
+}
+
+pub fn fee_token_write(e: &Env, fee_token: &Option<FeeToken>) {
+    match fee_token {
+        Some(fee_token) => e.storage().instance().set(&DataKey::FeeToken, fee_token),
+        None => e.storage().instance().remove(&DataKey::FeeToken),
+    }
+}
+
+// Price of one unit of the token in fee token units, scaled by PRICE_DECIMALS.
+pub fn fee_token_price_get(e: &Env, token: &Address) -> Option<u128> {
+    match fee_change_due(e, &FeeChange::FeeTokenPrice(token.clone(), 0)) {
+        Some(FeeChange::FeeTokenPrice(_, price)) => Some(price).filter(|price| *price > 0),
+        _ => e.storage().persistent().get(&DataKey::FeeTokenPrice(token.clone())),
+    }
+}
+
+// Schedules the token's price from `effective_ledger` on, a zero price removes it.
+// Must be authorized by a fee manager.
+pub fn fee_token_price_set(e: &Env, caller: &Address, token: &Address, price: u128, effective_ledger: u32) -> Result<(), SwapError> {
+    require_role(e, Role::FeeManager, caller)?;
+
+    fee_change_schedule(e, &FeeChange::FeeTokenPrice(token.clone(), price), effective_ledger)
+}
+
+pub fn fee_token_price_write(e: &Env, token: &Address, price: u128) {
+    let key = DataKey::FeeTokenPrice(token.clone());
+    if price == 0 {
+        e.storage().persistent().remove(&key);
+    }
+    else {
+        e.storage().persistent().set(&key, &price);
+        e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+    }
+}
+
+pub fn fee_token_opted_in(e: &Env, account: &Address) -> bool {
+    let key = DataKey::FeeTokenOptIn(account.clone());
+    e.storage().persistent().get(&key).unwrap_or(false)
+}
+
+// Must be authorized by the account.
+pub fn fee_token_opt_in(e: &Env, account: &Address, enabled: bool) {
+    account.require_auth();
+
+    let key = DataKey::FeeTokenOptIn(account.clone());
+    if enabled {
+        e.storage().persistent().set(&key, &true);
+        e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+    }
+    else {
+        e.storage().persistent().remove(&key);
+    }
+}
+
+// Token and amount the account pays a fee of `fee_amount` of `token` in.
+// Falls back to the traded token unless the account opted in and the token has a price.
+pub fn fee_token_quote(e: &Env, account: &Address, token: &Address, fee_amount: u64) -> (Address, u64) {
+    let fallback = (token.clone(), fee_amount);
+    if fee_amount == 0 || !fee_token_opted_in(e, account) {
+        return fallback;
+    }
+    let Some(fee_token) = fee_token_get(e) else {
+        return fallback;
+    };
+    let price = if fee_token.token == *token {
+        u128::pow(10, PRICE_DECIMALS)
+    }
+    else {
+        match fee_token_price_get(e, token) {
+            Some(price) => price,
+            None => return fallback,
+        }
+    };
+
+    let converted = (fee_amount as u128).checked_mul(price)
+        .map(|amount| amount / u128::pow(10, PRICE_DECIMALS))
+        .and_then(|amount| amount.checked_mul((SHARE_BPS_TOTAL - fee_token.discount_bps) as u128))
+        .map(|amount| amount / (SHARE_BPS_TOTAL as u128));
+    match converted.and_then(|amount| u64::try_from(amount).ok()) {
+        Some(amount) => (fee_token.token, amount),
+        None => fallback,
+    }
+}
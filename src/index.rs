@@ -0,0 +1,162 @@
+use soroban_sdk::{ Address, Env, Vec };
+
+use crate::storage_types::{ BALANCE_BUMP_AMOUNT, BALANCE_LIFETIME_THRESHOLD, INDEX_PAGE_SIZE,
+    DataKey, IndexKey, OfferInfo, OfferStatus
+};
+use crate::offer::{ offer_load, offer_write };
+
+
+// Secondary indexes of the open offers, by token pair and by offeror.
+// An offer is added when created and removed once it's completed, cancelled or reclaimed.
+// Ids are stored in pages of INDEX_PAGE_SIZE kept dense: a removed id is replaced by the last one,
+// whose position is updated in its offer.
+
+pub fn index_len(e: &Env, key: &IndexKey) -> u32 {
+    e.storage().persistent().get(&DataKey::IndexLen(key.clone())).unwrap_or(0)
+}
+
+pub fn index_page(e: &Env, key: &IndexKey, page: u32) -> Vec<u32> {
+    e.storage().persistent().get(&DataKey::IndexPage(key.clone(), page)).unwrap_or(Vec::new(e))
+}
+
+// Adds the offer to its indexes and records its positions in it, the offer is written by the caller.
+pub fn index_add(e: &Env, offer_id: u32, offer: &mut OfferInfo) {
+    offer.pair_pos = index_push(e, &pair_key(&offer.send_token, &offer.recv_token), offer_id);
+    offer.offeror_pos = index_push(e, &offeror_key(&offer.offeror), offer_id);
+}
+
+pub fn index_remove(e: &Env, offer: &OfferInfo) {
+    if let Some(moved_id) = index_swap_remove(e, &pair_key(&offer.send_token, &offer.recv_token), offer.pair_pos) {
+        if let Ok(mut moved) = offer_load(e, moved_id) {
+            moved.pair_pos = offer.pair_pos;
+            offer_write(e, moved_id, &moved);
+        }
+    }
+    if let Some(moved_id) = index_swap_remove(e, &offeror_key(&offer.offeror), offer.offeror_pos) {
+        if let Ok(mut moved) = offer_load(e, moved_id) {
+            moved.offeror_pos = offer.offeror_pos;
+            offer_write(e, moved_id, &moved);
+        }
+    }
+}
+
+// Extends the index pages holding the open offer, and its history page.
+pub fn index_bump(e: &Env, offer: &OfferInfo) {
+    let mut keys = Vec::new(e);
+    if offer.status == OfferStatus::ACTIVE {
+        for (key, pos) in [(pair_key(&offer.send_token, &offer.recv_token), offer.pair_pos), (offeror_key(&offer.offeror), offer.offeror_pos)] {
+            keys.push_back(DataKey::IndexPage(key.clone(), pos / INDEX_PAGE_SIZE));
+            keys.push_back(DataKey::IndexLen(key));
+        }
+    }
+    keys.push_back(DataKey::OfferorHistory(offer.offeror.clone(), offer.history_pos / INDEX_PAGE_SIZE));
+    keys.push_back(DataKey::OfferorHistoryLen(offer.offeror.clone()));
+
+    for key in keys.iter() {
+        if e.storage().persistent().has(&key) {
+            e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+        }
+    }
+}
+
+fn index_push(e: &Env, key: &IndexKey, offer_id: u32) -> u32 {
+    let pos = index_len(e, key);
+    let mut ids = index_page(e, key, pos / INDEX_PAGE_SIZE);
+    ids.push_back(offer_id);
+    index_page_write(e, key, pos / INDEX_PAGE_SIZE, &ids);
+    index_len_write(e, key, pos + 1);
+    pos
+}
+
+// Moves the last id of the index to `pos`, returns it unless it's the removed one.
+fn index_swap_remove(e: &Env, key: &IndexKey, pos: u32) -> Option<u32> {
+    let last = index_len(e, key).checked_sub(1)?;
+    let last_page = last / INDEX_PAGE_SIZE;
+    let mut ids = index_page(e, key, last_page);
+    let last_id = ids.pop_back()?;
+
+    let mut moved = None;
+    if pos < last {
+        let page = pos / INDEX_PAGE_SIZE;
+        if page == last_page {
+            ids.set(pos % INDEX_PAGE_SIZE, last_id);
+        }
+        else {
+            let mut page_ids = index_page(e, key, page);
+            page_ids.set(pos % INDEX_PAGE_SIZE, last_id);
+            index_page_write(e, key, page, &page_ids);
+        }
+        moved = Some(last_id);
+    }
+
+    index_page_write(e, key, last_page, &ids);
+    index_len_write(e, key, last);
+    moved
+}
+
+fn index_page_write(e: &Env, key: &IndexKey, page: u32, ids: &Vec<u32>) {
+    let key = DataKey::IndexPage(key.clone(), page);
+    if ids.is_empty() {
+        e.storage().persistent().remove(&key);
+        return;
+    }
+
+    e.storage().persistent().set(&key, ids);
+    e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+}
+
+fn index_len_write(e: &Env, key: &IndexKey, len: u32) {
+    let key = DataKey::IndexLen(key.clone());
+    if len == 0 {
+        e.storage().persistent().remove(&key);
+        return;
+    }
+
+    e.storage().persistent().set(&key, &len);
+    e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+}
+
+// Every offer created by an offeror along with its last status, in pages of INDEX_PAGE_SIZE.
+// An offer is added when created and its status updated once it's completed, cancelled or reclaimed.
+
+pub fn history_len(e: &Env, offeror: &Address) -> u32 {
+    e.storage().persistent().get(&DataKey::OfferorHistoryLen(offeror.clone())).unwrap_or(0)
+}
+
+pub fn history_page(e: &Env, offeror: &Address, page: u32) -> Vec<(u32, OfferStatus)> {
+    e.storage().persistent().get(&DataKey::OfferorHistory(offeror.clone(), page)).unwrap_or(Vec::new(e))
+}
+
+// Appends the offer to the history and records its position in it, the offer is written by the caller.
+pub fn history_add(e: &Env, offer_id: u32, offer: &mut OfferInfo) {
+    let pos = history_len(e, &offer.offeror);
+    let mut history = history_page(e, &offer.offeror, pos / INDEX_PAGE_SIZE);
+    history.push_back((offer_id, offer.status));
+    history_page_write(e, &offer.offeror, pos / INDEX_PAGE_SIZE, &history);
+
+    let key = DataKey::OfferorHistoryLen(offer.offeror.clone());
+    e.storage().persistent().set(&key, &(pos + 1));
+    e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+    offer.history_pos = pos;
+}
+
+pub fn history_write(e: &Env, offer_id: u32, offer: &OfferInfo) {
+    let page = offer.history_pos / INDEX_PAGE_SIZE;
+    let mut history = history_page(e, &offer.offeror, page);
+    history.set(offer.history_pos % INDEX_PAGE_SIZE, (offer_id, offer.status));
+    history_page_write(e, &offer.offeror, page, &history);
+}
+
+fn history_page_write(e: &Env, offeror: &Address, page: u32, history: &Vec<(u32, OfferStatus)>) {
+    let key = DataKey::OfferorHistory(offeror.clone(), page);
+    e.storage().persistent().set(&key, history);
+    e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+}
+
+pub fn pair_key(send_token: &Address, recv_token: &Address) -> IndexKey {
+    IndexKey::Pair(send_token.clone(), recv_token.clone())
+}
+
+pub fn offeror_key(offeror: &Address) -> IndexKey {
+    IndexKey::Offeror(offeror.clone())
+}
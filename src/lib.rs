@@ -4,70 +4,276 @@
 #![no_std]
 
 mod admin;
+mod error;
 mod storage_types;
 mod fee;
+mod fee_token;
 mod allow;
+mod index;
 mod offer;
+mod pause;
+mod role;
+mod tier;
+mod upgrade;
 
 
 use soroban_sdk::{
-    contract, contractimpl, Address, Env, /* BytesN */
+    contract, contractimpl, vec, Address, BytesN, Env, Vec,
 };
-use crate::storage_types::{ FeeInfo, INSTANCE_BUMP_AMOUNT, INSTANCE_LIFETIME_THRESHOLD};
-use crate::fee::{ fee_set, fee_get };
-use crate::allow::{ allow_set, allow_reset };
-use crate::offer::{ error, offer_count, offer_create, offer_accept, offer_update, offer_close, offer_load, offer_balances };
-use crate::admin::{ read_administrator, write_administrator, has_administrator };
+use crate::storage_types::{ CONTRACT_VERSION, SHARE_BPS_TOTAL, FeeChange, FeeInfo, FeeRecipient, FeeTier, FeeToken, FillPolicy, OfferStatus, OfferView, PairFee, PendingFee, Referral, Role };
+use crate::error::SwapError;
+use crate::fee::{ fee_set, fee_get, fee_check, fee_write, fee_pending_get, fee_changes_get, fee_history_add, fee_history_get, fee_cap_get, fee_cap_set, 
+    pair_fee_get, pair_fee_set, pair_fee_clear, fee_accrued_get, fee_withdraw, 
+    fee_recipients_get, fee_recipients_set, fee_recipients_write, referral_cap_get, referral_cap_set };
+use crate::fee_token::{ fee_token_get, fee_token_set, fee_token_price_get, fee_token_price_set, 
+    fee_token_opted_in, fee_token_opt_in };
+use crate::tier::{ tiers_get, tiers_set, tier_fee_rates, volume_get };
+use crate::allow::{ allow_set, allow_reset, allow_write };
+use crate::offer::{ offer_count, offer_create, offer_accept, offer_update, offer_increase, offer_decrease, offer_close, offer_reclaim, offer_load, offer_view, offer_bump, offer_balances, 
+    offer_list, offer_list_by_pair, offer_list_by_offeror, offer_history };
+use crate::upgrade::{ version_get, version_write, contract_upgrade, contract_migrate };
+use crate::pause::{ pause_get, token_pause_get, pause_set, token_pause_set };
+use crate::role::{ has_role, write_role, role_grant, role_revoke };
+use crate::admin::{ write_administrator, has_administrator, read_pending_administrator, 
+    admin_propose, admin_accept, admin_cancel };
 
 #[contract]
 pub struct TokenSwap;
 
 #[contractimpl]
 impl TokenSwap {
-    pub fn initialize(e: Env, admin: Address) {
+    pub fn initialize(e: Env, 
+        admin: Address, 
+        maker_fee_rate: u32, 
+        taker_fee_rate: u32, 
+        fee_wallet: Address, 
+        allowed_tokens: Vec<Address>
+    ) -> Result<(), SwapError> {
         if has_administrator(&e) {
-            panic!("already initialized");
+            return Err(SwapError::AlreadyInitialized);
         }
+        let fee_info = FeeInfo {maker_fee_rate, taker_fee_rate};
+        fee_check(&e, &fee_info)?;
+
         write_administrator(&e, &admin);
+        for role in [Role::FeeManager, Role::TokenLister, Role::Pauser, Role::Upgrader] {
+            write_role(&e, role, &admin);
+        }
+        fee_write(&e, &fee_info);
+        fee_history_add(&e, e.ledger().sequence(), &FeeChange::Fee(fee_info.clone()));
+        fee_recipients_write(&e, &vec![&e, FeeRecipient {wallet: fee_wallet, share_bps: SHARE_BPS_TOTAL}]);
+        for token in allowed_tokens.iter() {
+            allow_write(&e, &token);
+        }
+        version_write(&e, CONTRACT_VERSION);
+        Ok(())
+    }
+
+    pub fn upgrade(e: Env, caller: Address, new_wasm_hash: BytesN<32>) -> Result<(), SwapError> {
+        contract_upgrade(&e, &caller, &new_wasm_hash)
+    }
+
+    pub fn migrate(e: Env, caller: Address) -> Result<(), SwapError> {
+        contract_migrate(&e, &caller)
+    }
+
+    pub fn version(e: Env) -> u32 {
+        version_get(&e)
+    }
+
+    pub fn propose_admin(e: Env, new_admin: Address) -> Result<(), SwapError> {
+        admin_propose(&e, &new_admin)
+    }
+
+    pub fn accept_admin(e: Env) -> Result<(), SwapError> {
+        admin_accept(&e)
+    }
+
+    pub fn cancel_admin_proposal(e: Env) -> Result<(), SwapError> {
+        admin_cancel(&e)
+    }
+
+    pub fn get_pending_admin(e: Env) -> Option<Address> {
+        read_pending_administrator(&e)
+    }
+
+    pub fn grant_role(e: Env, role: Role, account: Address) -> Result<(), SwapError> {
+        role_grant(&e, role, &account)
+    }
+
+    pub fn revoke_role(e: Env, role: Role, account: Address) -> Result<(), SwapError> {
+        role_revoke(&e, role, &account)
+    }
+
+    pub fn has_role(e: Env, role: Role, account: Address) -> bool {
+        has_role(&e, role, &account)
+    }
+
+    pub fn set_fee(e: Env, 
+        caller: Address, 
+        maker_fee_rate: u32, 
+        taker_fee_rate: u32, 
+        effective_ledger: u32
+    ) -> Result<(), SwapError> {
+        let fee_info: FeeInfo = FeeInfo {maker_fee_rate, taker_fee_rate};
+        fee_set(&e, &caller, &fee_info, effective_ledger)
+    }
+
+    pub fn get_fee(e: Env) -> Result<(FeeInfo, Option<PendingFee>), SwapError> {
+        let fee_info: FeeInfo = fee_get(&e)?;
+        let pending_fee = fee_pending_get(&e)
+            .filter(|pending_fee| pending_fee.effective_ledger > e.ledger().sequence());
+        Ok((fee_info, pending_fee))
+    }
+
+    pub fn get_fee_history(e: Env, start: u32, limit: u32) -> (Vec<(u32, FeeChange)>, Option<u32>) {
+        fee_history_get(&e, start, limit)
     }
 
-    pub fn set_admin(e: Env, new_admin: Address) {
-        let admin = read_administrator(&e);
-        admin.require_auth();
+    pub fn get_pending_fee_changes(e: Env) -> Vec<(u32, FeeChange)> {
+        let mut changes = Vec::new(&e);
+        for (effective_ledger, change) in fee_changes_get(&e).iter() {
+            if effective_ledger > e.ledger().sequence() {
+                changes.push_back((effective_ledger, change));
+            }
+        }
+        changes
+    }
+
+    pub fn set_fee_recipients(e: Env, caller: Address, recipients: Vec<FeeRecipient>) -> Result<(), SwapError> {
+        fee_recipients_set(&e, &caller, &recipients)
+    }
+
+    pub fn get_fee_recipients(e: Env) -> Vec<FeeRecipient> {
+        fee_recipients_get(&e)
+    }
+
+    pub fn withdraw_fees(e: Env, caller: Address, token: Address, amount: u128) -> Result<(), SwapError> {
+        fee_withdraw(&e, &caller, &token, amount)
+    }
 
-        e.storage()
-            .instance()
-            .extend_ttl(INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT);
+    pub fn accrued_fees(e: Env, token: Address) -> u128 {
+        fee_accrued_get(&e, &token)
+    }
+
+    pub fn set_pair_fee(e: Env, 
+        caller: Address, 
+        send_token: Address, 
+        recv_token: Address, 
+        maker_fee_rate: u32, 
+        taker_fee_rate: u32, 
+        effective_ledger: u32
+    ) -> Result<(), SwapError> {
+        pair_fee_set(&e, &caller, &send_token, &recv_token, &PairFee {maker_fee_rate, taker_fee_rate}, effective_ledger)
+    }
+
+    pub fn clear_pair_fee(e: Env, 
+        caller: Address, 
+        send_token: Address, 
+        recv_token: Address, 
+        effective_ledger: u32
+    ) -> Result<(), SwapError> {
+        pair_fee_clear(&e, &caller, &send_token, &recv_token, effective_ledger)
+    }
+
+    pub fn get_pair_fee(e: Env, send_token: Address, recv_token: Address) -> Option<(u32, u32)> {
+        pair_fee_get(&e, &send_token, &recv_token)
+            .map(|pair_fee| (pair_fee.maker_fee_rate, pair_fee.taker_fee_rate))
+    }
+
+    pub fn set_fee_tiers(e: Env, caller: Address, token: Address, tiers: Vec<FeeTier>, effective_ledger: u32) -> Result<(), SwapError> {
+        tiers_set(&e, &caller, &token, &tiers, effective_ledger)
+    }
+
+    pub fn get_fee_tiers(e: Env, token: Address) -> Vec<FeeTier> {
+        tiers_get(&e, &token)
+    }
+
+    pub fn get_volume(e: Env, account: Address, token: Address) -> u128 {
+        volume_get(&e, &account, &token)
+    }
+
+    pub fn get_fee_for(e: Env, account: Address, send_token: Address, recv_token: Address) -> Result<(u32, u32), SwapError> {
+        tier_fee_rates(&e, &account, &send_token, &recv_token)
+    }
+
+    pub fn set_fee_cap(e: Env, fee_cap: u32) -> Result<(), SwapError> {
+        fee_cap_set(&e, fee_cap)
+    }
 
-        write_administrator(&e, &new_admin);
+    pub fn get_fee_cap(e: Env) -> u32 {
+        fee_cap_get(&e)
     }
 
-    pub fn set_fee(e: Env, fee_rate: u32, fee_wallet: Address) {
-        let fee_info: FeeInfo = FeeInfo {fee_rate, fee_wallet};
-        fee_set(&e, &fee_info);
+    pub fn set_referral_fee_cap(e: Env, referral_cap: u32) -> Result<(), SwapError> {
+        referral_cap_set(&e, referral_cap)
     }
 
-    pub fn get_fee(e: Env) -> (u32, Address) {
-        let fee_info: FeeInfo = fee_get(&e);
-        (fee_info.fee_rate, fee_info.fee_wallet)
+    pub fn get_referral_fee_cap(e: Env) -> u32 {
+        referral_cap_get(&e)
     }
 
-    pub fn allow_token(e: Env, token: Address) {
-        allow_set(&e, &token);
+    pub fn set_fee_token(e: Env, caller: Address, fee_token: Option<FeeToken>, effective_ledger: u32) -> Result<(), SwapError> {
+        fee_token_set(&e, &caller, &fee_token, effective_ledger)
     }
 
-    pub fn disallow_token(e: Env, token: Address) {
-        allow_reset(&e, &token);
+    pub fn get_fee_token(e: Env) -> Option<FeeToken> {
+        fee_token_get(&e)
     }
 
-    pub fn get_error(e: Env) -> u32 {
-        error(&e)
+    pub fn set_fee_token_price(e: Env, caller: Address, token: Address, price: u128, effective_ledger: u32) -> Result<(), SwapError> {
+        fee_token_price_set(&e, &caller, &token, price, effective_ledger)
+    }
+
+    pub fn get_fee_token_price(e: Env, token: Address) -> Option<u128> {
+        fee_token_price_get(&e, &token)
+    }
+
+    pub fn pay_fees_in_fee_token(e: Env, account: Address, enabled: bool) {
+        fee_token_opt_in(&e, &account, enabled)
+    }
+
+    pub fn pays_fees_in_fee_token(e: Env, account: Address) -> bool {
+        fee_token_opted_in(&e, &account)
+    }
+
+    pub fn allow_token(e: Env, caller: Address, token: Address) -> Result<(), SwapError> {
+        allow_set(&e, &caller, &token)
+    }
+
+    pub fn disallow_token(e: Env, caller: Address, token: Address) -> Result<(), SwapError> {
+        allow_reset(&e, &caller, &token)
+    }
+
+    pub fn pause(e: Env, caller: Address) -> Result<(), SwapError> {
+        pause_set(&e, &caller, true)
+    }
+
+    pub fn unpause(e: Env, caller: Address) -> Result<(), SwapError> {
+        pause_set(&e, &caller, false)
+    }
+
+    pub fn pause_token(e: Env, caller: Address, token: Address) -> Result<(), SwapError> {
+        token_pause_set(&e, &caller, &token, true)
+    }
+
+    pub fn unpause_token(e: Env, caller: Address, token: Address) -> Result<(), SwapError> {
+        token_pause_set(&e, &caller, &token, false)
+    }
+
+    pub fn is_paused(e: Env) -> bool {
+        pause_get(&e)
+    }
+
+    pub fn is_token_paused(e: Env, token: Address) -> bool {
+        token_pause_get(&e, &token)
     }
 
     pub fn count_offers(e: Env) -> u32 {
         offer_count(&e)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn create_offer(e: Env, 
         offeror: Address, 
         send_token: Address, 
@@ -75,21 +281,49 @@ impl TokenSwap {
         timestamp: u32, 
         send_amount: u64, 
         recv_amount: u64, 
-        min_recv_amount: u64
-    ) -> u32 {
-        let ret: u32 = offer_create(&e, &offeror, &send_token, &recv_token, timestamp, send_amount, recv_amount, min_recv_amount);
+        min_recv_amount: u64, 
+        acceptors: Vec<Address>, 
+        fill_policy: FillPolicy
+    ) -> Result<u32, SwapError> {
+        offer_create(&e, &offeror, &send_token, &recv_token, timestamp, send_amount, recv_amount, min_recv_amount, &acceptors, &fill_policy, &Referral::None)
+    }
 
-        ret
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_offer_with_referrer(e: Env, 
+        offeror: Address, 
+        send_token: Address, 
+        recv_token: Address, 
+        timestamp: u32, 
+        send_amount: u64, 
+        recv_amount: u64, 
+        min_recv_amount: u64, 
+        acceptors: Vec<Address>, 
+        fill_policy: FillPolicy, 
+        referral: Referral
+    ) -> Result<u32, SwapError> {
+        offer_create(&e, &offeror, &send_token, &recv_token, timestamp, send_amount, recv_amount, min_recv_amount, &acceptors, &fill_policy, &referral)
     }
 
     pub fn accept_offer(e: Env, 
         acceptor: Address, 
         offer_id: u32, 
-        amount: u64
-    ) -> u32 {
-        let ret: u32 = offer_accept(&e, &acceptor, offer_id, amount);
+        amount: u64, 
+        min_send_out: u64, 
+        deadline: u32
+    ) -> Result<(), SwapError> {
+        offer_accept(&e, &acceptor, offer_id, amount, min_send_out, deadline, &Referral::None)
+    }
 
-        ret
+    #[allow(clippy::too_many_arguments)]
+    pub fn accept_offer_with_referrer(e: Env, 
+        acceptor: Address, 
+        offer_id: u32, 
+        amount: u64, 
+        min_send_out: u64, 
+        deadline: u32, 
+        referral: Referral
+    ) -> Result<(), SwapError> {
+        offer_accept(&e, &acceptor, offer_id, amount, min_send_out, deadline, &referral)
     }
 
     pub fn update_offer(e: Env, 
@@ -97,30 +331,83 @@ impl TokenSwap {
         offer_id: u32, 
         recv_amount: u64, 
         min_recv_amount: u64
-    ) -> u32 {
-        let ret: u32 = offer_update(&e, &offeror, offer_id, recv_amount, min_recv_amount);
+    ) -> Result<(), SwapError> {
+        offer_update(&e, &offeror, offer_id, recv_amount, min_recv_amount)
+    }
+
+    pub fn increase_offer(e: Env, 
+        offeror: Address, 
+        offer_id: u32, 
+        extra_send_amount: u64
+    ) -> Result<(), SwapError> {
+        offer_increase(&e, &offeror, offer_id, extra_send_amount)
+    }
 
-        ret
+    pub fn decrease_offer(e: Env, 
+        offeror: Address, 
+        offer_id: u32, 
+        withdraw_amount: u64
+    ) -> Result<(), SwapError> {
+        offer_decrease(&e, &offeror, offer_id, withdraw_amount)
     }
 
     pub fn close_offer(e: Env, 
         offeror: Address,
         offer_id: u32
-    ) -> u32 {
-        let ret: u32 = offer_close(&e, &offeror, offer_id);
+    ) -> Result<(), SwapError> {
+        offer_close(&e, &offeror, offer_id)
+    }
+
+    pub fn reclaim_expired(e: Env, 
+        offer_id: u32
+    ) -> Result<(), SwapError> {
+        offer_reclaim(&e, offer_id)
+    }
 
-        ret
+    pub fn bump_offer(e: Env, 
+        offer_id: u32
+    ) -> Result<(), SwapError> {
+        offer_bump(&e, offer_id)
     }
 
     pub fn load_offer(e: Env, 
         offer_id: u32
-    ) -> (Address, Address, Address, u64, u64, u64, u32) {
-        let offer_info = offer_load(&e, offer_id);
-        (offer_info.offeror, 
-            offer_info.send_token, offer_info.recv_token, 
-            offer_info.send_amount, offer_info.recv_amount, offer_info.min_recv_amount, 
-            offer_info.status as u32
-        )
+    ) -> Result<OfferView, SwapError> {
+        let offer_info = offer_load(&e, offer_id)?;
+        Ok(offer_view(&e, &offer_info))
+    }
+
+    pub fn list_offers(e: Env, 
+        start: u32, 
+        limit: u32, 
+        status_filter: Option<OfferStatus>
+    ) -> (Vec<(u32, OfferView)>, Option<u32>) {
+        offer_list(&e, start, limit, status_filter)
+    }
+
+    pub fn list_offers_by_pair(e: Env, 
+        send_token: Address, 
+        recv_token: Address, 
+        start: u32, 
+        limit: u32
+    ) -> (Vec<(u32, OfferView)>, Option<u32>) {
+        offer_list_by_pair(&e, &send_token, &recv_token, start, limit)
+    }
+
+    pub fn list_offers_by_offeror(e: Env, 
+        offeror: Address, 
+        start: u32, 
+        limit: u32
+    ) -> (Vec<(u32, OfferView)>, Option<u32>) {
+        offer_list_by_offeror(&e, &offeror, start, limit)
+    }
+
+    pub fn get_offers_of(e: Env, 
+        offeror: Address, 
+        start: u32, 
+        limit: u32
+    ) -> (Vec<(u32, OfferStatus)>, Option<u32>) {
+        offer_history(&e, &offeror, start, limit)
     }
 
     pub fn check_balances(e: Env, 
@@ -1,14 +1,20 @@
 const OFFER: Symbol = symbol_short!("OFFER");
 
 use soroban_sdk::{
-    log, token, unwrap::UnwrapOptimized, Address, Env, symbol_short, /* BytesN, */ Symbol, 
+    log, token, Address, Env, symbol_short, /* BytesN, */ Symbol, Vec, 
     /* xdr::{ToXdr} */
 };
-use crate::storage_types::{ INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT, BALANCE_BUMP_AMOUNT, 
-    OfferStatus, OfferInfo, DataKey
+use crate::storage_types::{ INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT, BALANCE_BUMP_AMOUNT, BALANCE_LIFETIME_THRESHOLD, 
+    MAX_PAGE_SIZE, MAX_ACCEPTORS, INDEX_PAGE_SIZE, FillPolicy, OfferStatus, OfferInfo, OfferView, Referral, DataKey, IndexKey
 };
-use crate::fee::{ fee_check, fee_get, calculate_fee };
+use crate::index::{ index_len, index_page, index_add, index_remove, index_bump, history_len, history_page, history_add, history_write, pair_key, offeror_key };
+use crate::fee::{ fee_get, fee_rates, fee_collect, referral_check, calculate_fee };
+use crate::tier::{ tier_rate, volume_add };
+use crate::fee_token::{ fee_token_quote };
 use crate::allow::{ allow_get };
+use crate::pause::{ pause_check };
+use crate::admin::{ require_initialized };
+use crate::error::SwapError;
 
 
 /*
@@ -16,26 +22,16 @@ How this contract should be used:
 
 1. Call `create` once to create an offer and register its offeror.
 2. Offeror transfers send_amount of the `send_token` to the
-   contract address for swap. He may also update the recv_amount and/or min_recv_amount.
+   contract address for swap. He may also update the recv_amount and/or min_recv_amount,
+   or top up and withdraw part of the send_token at the same price.
 3. Acceptors may call `accept` to accept the offer. The contract will
    immediately perform the swap and send the respective amounts of `recv_token`
    and `send_token` to the offeror and acceptor respectively.
 4. Offeror may call `close` to claim any remaining `send_token` balance.
+5. Once the offer expires, anyone may call `reclaim_expired` to return the
+   remaining `send_token` balance to the offeror.
 */
 
-pub fn error(
-    e: &Env
-) -> u32 {
-    if !e.storage().instance().has(&DataKey::ErrorCode) {
-        return 1000;
-    }
-
-    let err_code: u32 = e.storage().instance().get(&DataKey::ErrorCode).unwrap_or(0);
-    err_code
-
-    // 1001
-}
-
 pub fn offer_count(
     e: &Env
 ) -> u32 {
@@ -44,7 +40,11 @@ pub fn offer_count(
 }
 
 // Creates the offer for offeror for the given token pair and initial amounts.
+// `timestamp` is the ledger timestamp at which the offer expires, 0 for no expiry.
+// Only `acceptors` may fill the offer unless it is empty, and only as `fill_policy` allows.
+// `referral` optionally gives a referrer its share of the maker fee.
 // See comment above the `Offer` struct for information on swap.
+#[allow(clippy::too_many_arguments)]
 pub fn offer_create(
     e: &Env,
     offeror: &Address,
@@ -54,14 +54,16 @@ pub fn offer_create(
     send_amount: u64,
     recv_amount: u64,
     min_recv_amount: u64,
-) -> u32 {
-    if !fee_check(e) {
-        // panic!("fee wasn't set");
-        return 101;
-    }
-    if !allow_get(e, &send_token.clone()) || !allow_get(e, &recv_token.clone()) {
-        // panic!("both tokens aren't allowed");
-        return 102;
+    acceptors: &Vec<Address>,
+    fill_policy: &FillPolicy,
+    referral: &Referral,
+) -> Result<u32, SwapError> {
+    require_initialized(e)?;
+    pause_check(e, send_token, recv_token)?;
+    let fee_info = fee_get(e)?;
+    referral_check(e, referral)?;
+    if !allow_get(e, send_token) || !allow_get(e, recv_token) {
+        return Err(SwapError::TokenNotAllowed);
     }
 
     let offer_count: u32 = e.storage().instance().get(&DataKey::OfferCount).unwrap_or(0);
@@ -69,50 +71,68 @@ pub fn offer_create(
     log!(e, "offer_id = {}", offer_id);
 
     if send_amount == 0 || recv_amount == 0 {
-        // panic!("zero amount is not allowed");
-        return 104;
+        return Err(SwapError::ZeroAmount);
     }
     if min_recv_amount > recv_amount {
-        // panic!("min_recv_amount can't be greater than recv_amount");
-        return 105;
+        return Err(SwapError::InvalidMinRecvAmount);
+    }
+    if timestamp != 0 && (timestamp as u64) <= e.ledger().timestamp() {
+        return Err(SwapError::InvalidExpiry);
     }
+    if acceptors.len() > MAX_ACCEPTORS {
+        return Err(SwapError::TooManyAcceptors);
+    }
+    offer_lot_check(fill_policy, recv_amount, min_recv_amount)?;
     
     // Authorize the `create` call by offeror to verify their identity.
     offeror.require_auth();
 
-    let fee_info = fee_get(e);
-    let fee_amount: u64 = calculate_fee(e, &fee_info.clone(), send_amount);
-    let transfer_amount = send_amount + fee_amount;
+    let (maker_fee_rate, _) = fee_rates(e, &fee_info, send_token, recv_token);
+    let maker_fee_rate = tier_rate(e, offeror, send_token, maker_fee_rate);
+    let fee_amount: u64 = calculate_fee(e, maker_fee_rate, send_amount);
+    let (fee_token, fee_amount) = fee_token_quote(e, offeror, send_token, fee_amount);
+    let transfer_amount = if fee_token == *send_token { offer_add(send_amount, fee_amount)? } else { send_amount };
     
     let contract = e.current_contract_address();
-    let send_token_client = token::Client::new(e, &send_token.clone());
-
-    if send_token_client.balance(&offeror) < (transfer_amount as i128) {
-        // panic!("insufficient balance");
-        return 106;
-    }
-    if send_token_client.allowance(&offeror, &contract) < (transfer_amount as i128) {
-        // panic!(e, "insufficient creator's allowance");
-        send_token_client.approve(&offeror, &contract, &(transfer_amount as i128), &(e.ledger().sequence() + BALANCE_BUMP_AMOUNT));
-        // return 107;
-    }
-
-    send_token_client.transfer(&offeror, &contract, &(send_amount as i128));
-    send_token_client.transfer(&offeror, &fee_info.fee_wallet, &(fee_amount as i128));
-
-    offer_write(
-        e,
-        offer_id,
-        &OfferInfo {
-            offeror: offeror.clone(),
-            send_token: send_token.clone(),
-            recv_token: recv_token.clone(),
-            send_amount,
-            recv_amount,
-            min_recv_amount,
-            status: OfferStatus::ACTIVE,
-        },
-    );
+    let send_token_client = token::Client::new(e, send_token);
+
+    if send_token_client.balance(offeror) < (transfer_amount as i128) {
+        return Err(SwapError::InsufficientBalance);
+    }
+    if fee_token != *send_token && token::Client::new(e, &fee_token).balance(offeror) < (fee_amount as i128) {
+        return Err(SwapError::InsufficientBalance);
+    }
+    if send_token_client.allowance(offeror, &contract) < (transfer_amount as i128) {
+        send_token_client.approve(offeror, &contract, &(transfer_amount as i128), &(e.ledger().sequence() + BALANCE_BUMP_AMOUNT));
+    }
+
+    // The fee is escrowed by the contract along with the offered amount.
+    send_token_client.transfer(offeror, &contract, &(transfer_amount as i128));
+    if fee_token != *send_token {
+        token::Client::new(e, &fee_token).transfer(offeror, &contract, &(fee_amount as i128));
+    }
+
+    let mut offer = OfferInfo {
+        offeror: offeror.clone(),
+        send_token: send_token.clone(),
+        recv_token: recv_token.clone(),
+        send_amount,
+        recv_amount,
+        min_recv_amount,
+        expiry: timestamp as u64,
+        fee_token,
+        fee_escrow: fee_amount,
+        referral: referral.clone(),
+        acceptors: acceptors.clone(),
+        fill_policy: fill_policy.clone(),
+        pair_pos: 0,
+        offeror_pos: 0,
+        history_pos: 0,
+        status: OfferStatus::ACTIVE,
+    };
+    index_add(e, offer_id, &mut offer);
+    history_add(e, offer_id, &mut offer);
+    offer_write(e, offer_id, &offer);
     let new_offer_count: u32 = offer_count + 1;
     e.storage().instance().set(&DataKey::OfferCount, &new_offer_count);
     e.storage().instance().extend_ttl(INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT);
@@ -122,38 +142,55 @@ pub fn offer_create(
         (offer_id, offeror.clone(), send_token.clone(), recv_token.clone(), send_amount, recv_amount, min_recv_amount, timestamp)
     );
 
-    offer_id
+    Ok(offer_id)
 }
 
 // Swaps `amount` of recv_token from acceptor for `send_token` amount calculated by the amount.
 // acceptor needs to authorize the `swap` call and internal `transfer` call to the contract address.
+// The fill is rejected if it pays out less than `min_send_out` or lands after the `deadline` ledger, 0 for none.
+// `referral` optionally gives a referrer its share of the taker fee.
+#[allow(clippy::too_many_arguments)]
 pub fn offer_accept(e: &Env, 
     acceptor: &Address, 
     offer_id: u32,
-    amount: u64
-) -> u32 {
-    if !e.storage().instance().has(&DataKey::RegOffers(offer_id)) {
-        // panic!("can't find offer");
-        return 110;
+    amount: u64,
+    min_send_out: u64,
+    deadline: u32,
+    referral: &Referral
+) -> Result<(), SwapError> {
+    require_initialized(e)?;
+    if deadline != 0 && e.ledger().sequence() > deadline {
+        return Err(SwapError::DeadlinePassed);
     }
+    let mut offer = offer_load(e, offer_id)?;
 
-    let mut offer = offer_load(e, offer_id);
-
-    if !fee_check(e) {
-        // panic!("fee isn't set");
-        return 111;
-    }
+    pause_check(e, &offer.send_token, &offer.recv_token)?;
+    let fee_info = fee_get(e)?;
+    referral_check(e, referral)?;
     if offer.status != OfferStatus::ACTIVE {
-        // panic!("offer not available");
-        return 112;
+        return Err(SwapError::OfferNotActive);
+    }
+    if offer_expired(e, &offer) {
+        return Err(SwapError::OfferExpired);
+    }
+    // self-fills would only inflate the offeror's traded volume
+    if *acceptor == offer.offeror || (!offer.acceptors.is_empty() && !offer.acceptors.contains(acceptor)) {
+        return Err(SwapError::InvalidAcceptor);
     }
     if offer.recv_amount < amount {
-        panic!("amount is greater than max_recv_amount");
-        // return 113;
+        return Err(SwapError::AmountAboveRecvAmount);
     }
     if amount < offer.min_recv_amount {
-        panic!("amount must be more than min_recv_amount");
-        // return 114;
+        return Err(SwapError::AmountBelowMinRecvAmount);
+    }
+    match offer.fill_policy {
+        FillPolicy::Partial => {},
+        FillPolicy::AllOrNone => if amount != offer.recv_amount {
+            return Err(SwapError::FillNotAllowed);
+        },
+        FillPolicy::MinLot(lot_size) => if !amount.is_multiple_of(lot_size) {
+            return Err(SwapError::FillNotAllowed);
+        },
     }
     
     // acceptor needs to authorize the trade.
@@ -163,22 +200,35 @@ pub fn offer_accept(e: &Env,
     let send_token_client = token::Client::new(e, &offer.send_token);
     let recv_token_client = token::Client::new(e, &offer.recv_token);
 
-    let fee_info = fee_get(e);
-    let fee_amount: u64 = calculate_fee(e, &fee_info.clone(), amount);
+    let (_, taker_fee_rate) = fee_rates(e, &fee_info, &offer.send_token, &offer.recv_token);
+    let taker_fee_rate = tier_rate(e, acceptor, &offer.recv_token, taker_fee_rate);
+    let fee_amount: u64 = calculate_fee(e, taker_fee_rate, amount);
+    let (fee_token, fee_amount) = fee_token_quote(e, acceptor, &offer.recv_token, fee_amount);
+    let pay_amount = if fee_token == offer.recv_token { offer_add(amount, fee_amount)? } else { amount };
     let contract = e.current_contract_address();
     
-    if recv_token_client.balance(&acceptor) < (amount + fee_amount) as i128 {
-        // panic!("insufficient balance");
-        return 115;
+    if recv_token_client.balance(acceptor) < pay_amount as i128 {
+        return Err(SwapError::InsufficientBalance);
+    }
+    if fee_token != offer.recv_token && token::Client::new(e, &fee_token).balance(acceptor) < fee_amount as i128 {
+        return Err(SwapError::InsufficientBalance);
     }
-    if recv_token_client.allowance(&acceptor, &contract.clone()) < (amount + fee_amount) as i128 {
-        // panic!("insufficient allowance");
-        recv_token_client.approve(&acceptor, &contract, &((amount + fee_amount) as i128), &(e.ledger().sequence() + BALANCE_BUMP_AMOUNT));
-        // return 116;
+    if recv_token_client.allowance(acceptor, &contract) < pay_amount as i128 {
+        recv_token_client.approve(acceptor, &contract, &(pay_amount as i128), &(e.ledger().sequence() + BALANCE_BUMP_AMOUNT));
     }
 
     // Compute the amount of send_token that acceptor can receive.
-    let prop_send_amount = amount.checked_mul(offer.send_amount).unwrap_optimized() / offer.recv_amount;
+    let prop_send_amount = offer_scale(amount, offer.send_amount, offer.recv_amount)?;
+    if prop_send_amount < min_send_out {
+        return Err(SwapError::SendAmountBelowMin);
+    }
+    // and the share of the escrowed maker fee it uses up, all of it once the offer completes.
+    let maker_fee_amount = if amount == offer.recv_amount {
+        offer.fee_escrow
+    }
+    else {
+        offer_scale(offer.fee_escrow, prop_send_amount, offer.send_amount)?
+    };
 
     // Perform the trade in 3 `transfer` steps.
     // Note, that we don't need to verify any balances - the contract would
@@ -191,18 +241,30 @@ pub fn offer_accept(e: &Env,
     // the contract address allows building more transparent signature
     // payload where the acceptor doesn't need to worry about sending token to
     // some 'unknown' third party.
-    recv_token_client.transfer(&acceptor, &fee_info.fee_wallet, &(fee_amount as i128));
+    if fee_amount > 0 {
+        token::Client::new(e, &fee_token).transfer(acceptor, &contract, &(fee_amount as i128));
+        fee_collect(e, offer_id, &fee_token, fee_amount, referral);
+    }
     // Transfer the `recv_token` to the offeror immediately.
-    recv_token_client.transfer(&acceptor, &offer.offeror, &(amount as i128));
+    recv_token_client.transfer(acceptor, &offer.offeror, &(amount as i128));
     // Transfer the `send_token` from contract to acceptor.
-    send_token_client.transfer(&contract, &acceptor, &(prop_send_amount as i128));
+    send_token_client.transfer(&contract, acceptor, &(prop_send_amount as i128));
+    // Charge the filled part of the maker fee.
+    fee_collect(e, offer_id, &offer.fee_token, maker_fee_amount, &offer.referral);
+
+    // Both parties traded
+    volume_add(e, acceptor, &offer.recv_token, amount);
+    volume_add(e, &offer.offeror, &offer.send_token, prop_send_amount);
 
     // Update Offer
     offer.send_amount -= prop_send_amount;
     offer.recv_amount -= amount;
+    offer.fee_escrow -= maker_fee_amount;
 
     if offer.recv_amount == 0 {
         offer.status = OfferStatus::COMPLETE;
+        index_remove(e, &offer);
+        history_write(e, offer_id, &offer);
         // emit OfferCompleted event
         e.events().publish((OFFER, symbol_short!("OComplete")), 
             offer_id
@@ -219,7 +281,7 @@ pub fn offer_accept(e: &Env,
         (acceptor.clone(), offer_id, amount)
     );
 
-    0
+    Ok(())
 }
 
 // Updates offer
@@ -229,45 +291,134 @@ pub fn offer_update(e: &Env,
     offer_id: u32, 
     recv_amount: u64, 
     min_recv_amount: u64
-) -> u32 {
+) -> Result<(), SwapError> {
+    require_initialized(e)?;
     if recv_amount == 0 {
-        // panic!("zero amount is not allowed");
-        return 121;
+        return Err(SwapError::ZeroAmount);
     }
     if min_recv_amount > recv_amount {
-        // panic!("min_recv_amount can't be greater than recv_amount");
-        return 122;
+        return Err(SwapError::InvalidMinRecvAmount);
     }
 
-    if !e.storage().instance().has(&DataKey::RegOffers(offer_id)) {
-        // panic!("can't find offer");
-        // return 123;
-        return e.ledger().sequence();
+    let mut offer = offer_load_open(e, offeror, offer_id)?;
+    offer_lot_check(&offer.fill_policy, recv_amount, min_recv_amount)?;
+
+    offeror.clone().require_auth();
+
+    offer.recv_amount = recv_amount;
+    offer.min_recv_amount = min_recv_amount;
+    offer_write(e, offer_id, &offer);
+
+    // emit OfferUpdated event
+    e.events().publish((OFFER, symbol_short!("OUpdate")), 
+        (offeror.clone(), offer_id, recv_amount, min_recv_amount)
+    );
+
+    Ok(())
+}
+
+// Adds `extra_send_amount` to the offer's escrow at the same price.
+// The maker fee is escrowed for the extra amount at the offer's rate.
+// Must be authorized by offeror.
+pub fn offer_increase(e: &Env, 
+    offeror: &Address, 
+    offer_id: u32, 
+    extra_send_amount: u64
+) -> Result<(), SwapError> {
+    require_initialized(e)?;
+    if extra_send_amount == 0 {
+        return Err(SwapError::ZeroAmount);
     }
 
-    let mut offer = offer_load(e, offer_id);
+    let mut offer = offer_load_open(e, offeror, offer_id)?;
+    pause_check(e, &offer.send_token, &offer.recv_token)?;
+    let send_amount = offer_add(offer.send_amount, extra_send_amount)?;
+    let recv_amount = offer_add(offer.recv_amount, offer_scale(extra_send_amount, offer.recv_amount, offer.send_amount)?)?;
+    let extra_fee_amount = offer_scale(extra_send_amount, offer.fee_escrow, offer.send_amount)?;
+    let fee_escrow = offer_add(offer.fee_escrow, extra_fee_amount)?;
+    let transfer_amount = if offer.fee_token == offer.send_token { offer_add(extra_send_amount, extra_fee_amount)? } else { extra_send_amount };
+    offer_lot_check(&offer.fill_policy, recv_amount, offer.min_recv_amount)?;
+
+    offeror.clone().require_auth();
+
+    let contract = e.current_contract_address();
+    let send_token_client = token::Client::new(e, &offer.send_token);
+    let fee_token_client = token::Client::new(e, &offer.fee_token);
+
+    if send_token_client.balance(offeror) < (transfer_amount as i128) {
+        return Err(SwapError::InsufficientBalance);
+    }
+    if offer.fee_token != offer.send_token && fee_token_client.balance(offeror) < (extra_fee_amount as i128) {
+        return Err(SwapError::InsufficientBalance);
+    }
+    if send_token_client.allowance(offeror, &contract) < (transfer_amount as i128) {
+        send_token_client.approve(offeror, &contract, &(transfer_amount as i128), &(e.ledger().sequence() + BALANCE_BUMP_AMOUNT));
+    }
 
-    if offer.offeror != offeror.clone() {
-        // panic!("invalid offeror");
-        return 124;
+    send_token_client.transfer(offeror, &contract, &(transfer_amount as i128));
+    if offer.fee_token != offer.send_token && extra_fee_amount > 0 {
+        fee_token_client.transfer(offeror, &contract, &(extra_fee_amount as i128));
     }
-    if offer.status != OfferStatus::ACTIVE {
-        // panic!("offer not available");
-        return 125;
+
+    offer.send_amount = send_amount;
+    offer.recv_amount = recv_amount;
+    offer.fee_escrow = fee_escrow;
+    offer_write(e, offer_id, &offer);
+
+    // emit OfferIncreased event
+    e.events().publish((OFFER, symbol_short!("OIncrease")), 
+        (offeror.clone(), offer_id, offer.send_amount, offer.recv_amount)
+    );
+
+    Ok(())
+}
+
+// Withdraws `withdraw_amount` from the offer's escrow at the same price.
+// The unused maker fee for the withdrawn amount is refunded.
+// Must be authorized by offeror.
+pub fn offer_decrease(e: &Env, 
+    offeror: &Address, 
+    offer_id: u32, 
+    withdraw_amount: u64
+) -> Result<(), SwapError> {
+    require_initialized(e)?;
+    if withdraw_amount == 0 {
+        return Err(SwapError::ZeroAmount);
     }
 
+    let mut offer = offer_load_open(e, offeror, offer_id)?;
+    // withdrawing everything is closing the offer
+    if withdraw_amount >= offer.send_amount {
+        return Err(SwapError::AmountAboveSendAmount);
+    }
+    let send_amount = offer.send_amount - withdraw_amount;
+    let recv_amount = offer_scale(send_amount, offer.recv_amount, offer.send_amount)?;
+    if recv_amount == 0 {
+        return Err(SwapError::ZeroAmount);
+    }
+    offer_lot_check(&offer.fill_policy, recv_amount, offer.min_recv_amount.min(recv_amount))?;
+
     offeror.clone().require_auth();
 
+    let refund_fee_amount = offer_scale(withdraw_amount, offer.fee_escrow, offer.send_amount)?;
+    let contract = e.current_contract_address();
+    token::Client::new(e, &offer.send_token).transfer(&contract, offeror, &(withdraw_amount as i128));
+    if refund_fee_amount > 0 {
+        token::Client::new(e, &offer.fee_token).transfer(&contract, offeror, &(refund_fee_amount as i128));
+    }
+
+    offer.send_amount = send_amount;
     offer.recv_amount = recv_amount;
-    offer.min_recv_amount = min_recv_amount;
+    offer.min_recv_amount = offer.min_recv_amount.min(recv_amount);
+    offer.fee_escrow -= refund_fee_amount;
     offer_write(e, offer_id, &offer);
 
-    // emit OfferUpdated event
-    e.events().publish((OFFER, symbol_short!("OUpdate")), 
-        (offeror.clone(), offer_id, recv_amount, min_recv_amount)
+    // emit OfferDecreased event
+    e.events().publish((OFFER, symbol_short!("ODecrease")), 
+        (offeror.clone(), offer_id, offer.send_amount, offer.recv_amount)
     );
 
-    0
+    Ok(())
 }
 
 // Cancel offer
@@ -275,40 +426,168 @@ pub fn offer_update(e: &Env,
 pub fn offer_close(e: &Env, 
     offeror: &Address, 
     offer_id: u32
-) -> u32 {
-    if !e.storage().instance().has(&DataKey::RegOffers(offer_id)) {
-        // panic!("can't find offer");
-        return 131;
-    }
-
-    let mut offer = offer_load(e, offer_id);
+) -> Result<(), SwapError> {
+    require_initialized(e)?;
+    let mut offer = offer_load(e, offer_id)?;
 
-    if offer.offeror != offeror.clone() {
-        // panic!("invalid offeror");
-        return 132;
+    if offer.offeror != *offeror {
+        return Err(SwapError::InvalidOfferor);
     }
     if offer.status != OfferStatus::ACTIVE {
-        // panic!("offer not available");
-        return 133;
+        return Err(SwapError::OfferNotActive);
     }
 
     offeror.clone().require_auth();
     
-    token::Client::new(e, &offer.send_token).transfer(
-        &e.current_contract_address(),
-        &offeror,
-        &(offer.send_amount as i128),
-    );
+    offer_refund(e, &mut offer);
 
     offer.status = OfferStatus::CANCEL;
     offer_write(e, offer_id, &offer);
+    index_remove(e, &offer);
+    history_write(e, offer_id, &offer);
 
     // emit OfferRevoked event
     e.events().publish((OFFER, symbol_short!("ORevoke")), 
         (offeror.clone(), offer_id)
     );
 
-    0
+    Ok(())
+}
+
+// Returns the remaining send_token of an expired offer to its offeror.
+// Anyone may call it.
+pub fn offer_reclaim(e: &Env, 
+    offer_id: u32
+) -> Result<(), SwapError> {
+    require_initialized(e)?;
+    let mut offer = offer_load(e, offer_id)?;
+
+    if offer.status != OfferStatus::ACTIVE {
+        return Err(SwapError::OfferNotActive);
+    }
+    if !offer_expired(e, &offer) {
+        return Err(SwapError::OfferNotExpired);
+    }
+
+    offer_refund(e, &mut offer);
+
+    offer.status = OfferStatus::EXPIRED;
+    offer_write(e, offer_id, &offer);
+    index_remove(e, &offer);
+    history_write(e, offer_id, &offer);
+
+    // emit OfferExpired event
+    e.events().publish((OFFER, symbol_short!("OExpire")), 
+        (offer.offeror.clone(), offer_id)
+    );
+
+    Ok(())
+}
+
+// Returns the remaining send_token and the unused maker fee to the offeror.
+fn offer_refund(e: &Env, offer: &mut OfferInfo) {
+    let contract = e.current_contract_address();
+    token::Client::new(e, &offer.send_token).transfer(&contract, &offer.offeror, &(offer.send_amount as i128));
+    if offer.fee_escrow > 0 {
+        token::Client::new(e, &offer.fee_token).transfer(&contract, &offer.offeror, &(offer.fee_escrow as i128));
+        offer.fee_escrow = 0;
+    }
+}
+
+// Scans up to `limit` offer ids from `start`, optionally keeping only the offers with the given status.
+// Also returns the id to continue the scan from, if any.
+pub fn offer_list(e: &Env, 
+    start: u32, 
+    limit: u32, 
+    status_filter: Option<OfferStatus>
+) -> (Vec<(u32, OfferView)>, Option<u32>) {
+    let count = offer_count(e);
+    let end = count.min(start.saturating_add(limit.min(MAX_PAGE_SIZE)));
+    let mut offers = Vec::new(e);
+
+    for offer_id in start..end {
+        if let Ok(offer) = offer_load(e, offer_id) {
+            let offer = offer_view(e, &offer);
+            if status_filter.is_none_or(|status| status == offer.status) {
+                offers.push_back((offer_id, offer));
+            }
+        }
+    }
+
+    (offers, (start < end && end < count).then_some(end))
+}
+
+// Lists up to `limit` open offers of the token pair, from position `start` of the pair index.
+// Also returns the position to continue from, if any.
+pub fn offer_list_by_pair(e: &Env, 
+    send_token: &Address, 
+    recv_token: &Address, 
+    start: u32, 
+    limit: u32
+) -> (Vec<(u32, OfferView)>, Option<u32>) {
+    offer_list_indexed(e, &pair_key(send_token, recv_token), start, limit)
+}
+
+// Lists up to `limit` open offers of the offeror, from position `start` of the offeror index.
+// Also returns the position to continue from, if any.
+pub fn offer_list_by_offeror(e: &Env, 
+    offeror: &Address, 
+    start: u32, 
+    limit: u32
+) -> (Vec<(u32, OfferView)>, Option<u32>) {
+    offer_list_indexed(e, &offeror_key(offeror), start, limit)
+}
+
+// Ids of up to `limit` offers created by the offeror with their statuses, from position `start` of its history.
+// Also returns the position to continue from, if any.
+pub fn offer_history(e: &Env, 
+    offeror: &Address, 
+    start: u32, 
+    limit: u32
+) -> (Vec<(u32, OfferStatus)>, Option<u32>) {
+    let len = history_len(e, offeror);
+    let end = len.min(start.saturating_add(limit.min(MAX_PAGE_SIZE)));
+    let mut history = Vec::new(e);
+
+    let mut page = Vec::new(e);
+    for pos in start..end {
+        if pos == start || pos % INDEX_PAGE_SIZE == 0 {
+            page = history_page(e, offeror, pos / INDEX_PAGE_SIZE);
+        }
+        let (offer_id, mut status) = page.get_unchecked(pos % INDEX_PAGE_SIZE);
+        // active offers may have expired since
+        if status == OfferStatus::ACTIVE {
+            if let Ok(offer) = offer_load(e, offer_id) {
+                status = offer_status(e, &offer);
+            }
+        }
+        history.push_back((offer_id, status));
+    }
+
+    (history, (start < end && end < len).then_some(end))
+}
+
+fn offer_list_indexed(e: &Env, 
+    key: &IndexKey, 
+    start: u32, 
+    limit: u32
+) -> (Vec<(u32, OfferView)>, Option<u32>) {
+    let len = index_len(e, key);
+    let end = len.min(start.saturating_add(limit.min(MAX_PAGE_SIZE)));
+    let mut offers = Vec::new(e);
+
+    let mut ids = Vec::new(e);
+    for pos in start..end {
+        if pos == start || pos % INDEX_PAGE_SIZE == 0 {
+            ids = index_page(e, key, pos / INDEX_PAGE_SIZE);
+        }
+        let offer_id = ids.get_unchecked(pos % INDEX_PAGE_SIZE);
+        if let Ok(offer) = offer_load(e, offer_id) {
+            offers.push_back((offer_id, offer_view(e, &offer)));
+        }
+    }
+
+    (offers, (start < end && end < len).then_some(end))
 }
 
 // Check balances
@@ -323,11 +602,91 @@ pub fn offer_balances(e: &Env,
     (send_token_client.balance(account) as u64, recv_token_client.balance(account) as u64)
 }
 
-pub fn offer_load(e: &Env, key: u32) -> OfferInfo {
-    e.storage().instance().get(&DataKey::RegOffers(key)).unwrap()
+// Extends the TTL of the offer entry and of its index and history pages so they don't get archived.
+// Anyone may call it.
+pub fn offer_bump(e: &Env, offer_id: u32) -> Result<(), SwapError> {
+    let offer = offer_load(e, offer_id)?;
+
+    let key = DataKey::RegOffers(offer_id);
+    e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+    index_bump(e, &offer);
+    Ok(())
+}
+
+// Offer as seen by clients, with its current status and without its index positions.
+pub fn offer_view(e: &Env, offer: &OfferInfo) -> OfferView {
+    OfferView {
+        offeror: offer.offeror.clone(),
+        send_token: offer.send_token.clone(),
+        recv_token: offer.recv_token.clone(),
+        send_amount: offer.send_amount,
+        recv_amount: offer.recv_amount,
+        min_recv_amount: offer.min_recv_amount,
+        expiry: offer.expiry,
+        fee_token: offer.fee_token.clone(),
+        fee_escrow: offer.fee_escrow,
+        referral: offer.referral.clone(),
+        acceptors: offer.acceptors.clone(),
+        fill_policy: offer.fill_policy.clone(),
+        status: offer_status(e, offer),
+    }
+}
+
+// Status of the offer as seen by acceptors, an active offer past its expiry is reported as EXPIRED.
+pub fn offer_status(e: &Env, offer: &OfferInfo) -> OfferStatus {
+    if offer.status == OfferStatus::ACTIVE && offer_expired(e, offer) {
+        OfferStatus::EXPIRED
+    }
+    else {
+        offer.status
+    }
+}
+
+// Loads an offer the offeror may still change.
+fn offer_load_open(e: &Env, offeror: &Address, offer_id: u32) -> Result<OfferInfo, SwapError> {
+    let offer = offer_load(e, offer_id)?;
+
+    if offer.offeror != *offeror {
+        return Err(SwapError::InvalidOfferor);
+    }
+    if offer.status != OfferStatus::ACTIVE {
+        return Err(SwapError::OfferNotActive);
+    }
+    if offer_expired(e, &offer) {
+        return Err(SwapError::OfferExpired);
+    }
+    Ok(offer)
+}
+
+// Fails unless the amounts can be filled in whole lots of a MinLot offer.
+fn offer_lot_check(fill_policy: &FillPolicy, recv_amount: u64, min_recv_amount: u64) -> Result<(), SwapError> {
+    if let FillPolicy::MinLot(lot_size) = fill_policy {
+        if *lot_size == 0 || !recv_amount.is_multiple_of(*lot_size) || !min_recv_amount.is_multiple_of(*lot_size) {
+            return Err(SwapError::InvalidFillPolicy);
+        }
+    }
+    Ok(())
+}
+
+// `amount * numerator / denominator`, rounded down and computed in u128 so large offers don't overflow.
+fn offer_scale(amount: u64, numerator: u64, denominator: u64) -> Result<u64, SwapError> {
+    u64::try_from((amount as u128) * (numerator as u128) / (denominator as u128)).map_err(|_| SwapError::AmountOverflow)
+}
+
+fn offer_add(amount: u64, extra_amount: u64) -> Result<u64, SwapError> {
+    amount.checked_add(extra_amount).ok_or(SwapError::AmountOverflow)
+}
+
+fn offer_expired(e: &Env, offer: &OfferInfo) -> bool {
+    offer.expiry != 0 && e.ledger().timestamp() >= offer.expiry
+}
+
+pub fn offer_load(e: &Env, key: u32) -> Result<OfferInfo, SwapError> {
+    e.storage().persistent().get(&DataKey::RegOffers(key)).ok_or(SwapError::OfferNotFound)
 }
 
-fn offer_write(e: &Env, key: u32, offer: &OfferInfo) {
-    e.storage().instance().set(&DataKey::RegOffers(key), offer);
-    // e.storage().instance().bump(INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT);
+pub fn offer_write(e: &Env, key: u32, offer: &OfferInfo) {
+    let key = DataKey::RegOffers(key);
+    e.storage().persistent().set(&key, offer);
+    e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
 }
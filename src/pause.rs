@@ -0,0 +1,61 @@
+const PAUSE: Symbol = symbol_short!("PAUSE");
+
+use soroban_sdk::{ symbol_short, Address, Env, Symbol };
+
+use crate::storage_types::{ DataKey, Role };
+use crate::error::SwapError;
+
+use crate::role::{
+    require_role,
+};
+
+
+pub fn pause_get(e: &Env) -> bool {
+    e.storage().instance().get::<_, bool>(&DataKey::Paused).unwrap_or(false)
+}
+
+pub fn token_pause_get(e: &Env, token: &Address) -> bool {
+    let key = DataKey::TokenPaused(token.clone());
+    e.storage().instance().get::<_, bool>(&key).unwrap_or(false)
+}
+
+// Fails if the contract or either token of the pair is paused.
+pub fn pause_check(e: &Env, send_token: &Address, recv_token: &Address) -> Result<(), SwapError> {
+    if pause_get(e) {
+        return Err(SwapError::ContractPaused);
+    }
+    if token_pause_get(e, send_token) || token_pause_get(e, recv_token) {
+        return Err(SwapError::TokenPaused);
+    }
+    Ok(())
+}
+
+// Must be authorized by a pauser.
+pub fn pause_set(e: &Env, caller: &Address, paused: bool) -> Result<(), SwapError> {
+    require_role(e, Role::Pauser, caller)?;
+    e.storage().instance().set(&DataKey::Paused, &paused);
+
+    // emit Paused/Unpaused event
+    let topic = if paused { symbol_short!("Paused") } else { symbol_short!("Unpaused") };
+    e.events().publish((PAUSE, topic), caller.clone());
+
+    Ok(())
+}
+
+// Must be authorized by a pauser.
+pub fn token_pause_set(e: &Env, caller: &Address, token: &Address, paused: bool) -> Result<(), SwapError> {
+    require_role(e, Role::Pauser, caller)?;
+    let key = DataKey::TokenPaused(token.clone());
+    if paused {
+        e.storage().instance().set(&key, &true);
+    }
+    else {
+        e.storage().instance().remove(&key);
+    }
+
+    // emit TokenPaused/TokenUnpaused event
+    let topic = if paused { symbol_short!("TPaused") } else { symbol_short!("TUnpaused") };
+    e.events().publish((PAUSE, topic), (caller.clone(), token.clone()));
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+const ROLE: Symbol = symbol_short!("ROLE");
+
+use soroban_sdk::{ symbol_short, Address, Env, Symbol };
+
+use crate::storage_types::{ DataKey, Role };
+use crate::error::SwapError;
+
+use crate::admin::{
+    read_administrator,
+};
+
+
+pub fn has_role(e: &Env, role: Role, account: &Address) -> bool {
+    let key = DataKey::Role(role, account.clone());
+    e.storage().instance().get::<_, bool>(&key).unwrap_or(false)
+}
+
+// Checks that the account holds the role and authorized the call.
+pub fn require_role(e: &Env, role: Role, account: &Address) -> Result<(), SwapError> {
+    read_administrator(e)?;
+    if !has_role(e, role, account) {
+        return Err(SwapError::MissingRole);
+    }
+
+    account.require_auth();
+    Ok(())
+}
+
+pub fn write_role(e: &Env, role: Role, account: &Address) {
+    let key = DataKey::Role(role, account.clone());
+    e.storage().instance().set(&key, &true);
+}
+
+// Must be authorized by admin.
+pub fn role_grant(e: &Env, role: Role, account: &Address) -> Result<(), SwapError> {
+    let admin = read_administrator(e)?;
+    admin.require_auth();
+
+    write_role(e, role, account);
+
+    // emit RoleGranted event
+    e.events().publish((ROLE, symbol_short!("RGrant")), 
+        (role, account.clone())
+    );
+
+    Ok(())
+}
+
+// Must be authorized by admin.
+pub fn role_revoke(e: &Env, role: Role, account: &Address) -> Result<(), SwapError> {
+    let admin = read_administrator(e)?;
+    admin.require_auth();
+
+    if !has_role(e, role, account) {
+        return Err(SwapError::MissingRole);
+    }
+    e.storage().instance().remove(&DataKey::Role(role, account.clone()));
+
+    // emit RoleRevoked event
+    e.events().publish((ROLE, symbol_short!("RRevoke")), 
+        (role, account.clone())
+    );
+
+    Ok(())
+}
+
+// Moves every role held by `from` to `to`, when the admin is handed over.
+pub fn role_transfer(e: &Env, from: &Address, to: &Address) {
+    for role in [Role::FeeManager, Role::TokenLister, Role::Pauser, Role::Upgrader] {
+        if !has_role(e, role, from) {
+            continue;
+        }
+        e.storage().instance().remove(&DataKey::Role(role, from.clone()));
+        write_role(e, role, to);
+
+        // emit RoleRevoked and RoleGranted events
+        e.events().publish((ROLE, symbol_short!("RRevoke")), 
+            (role, from.clone())
+        );
+        e.events().publish((ROLE, symbol_short!("RGrant")), 
+            (role, to.clone())
+        );
+    }
+}
@@ -1,28 +1,118 @@
-use soroban_sdk::{ contracttype, Address/* , BytesN */ };
+use soroban_sdk::{ contracttype, Address, Vec/* , BytesN */ };
 
 
+pub(crate) const CONTRACT_VERSION: u32 = 1;
+
 pub(crate) const FEE_DECIMALS: u32 = 4;
+pub(crate) const MAX_FEE_RATE: u32 = 1000; // 10%, the admin may only lower the cap
+pub(crate) const SHARE_BPS_TOTAL: u32 = 10000;
+pub(crate) const MAX_FEE_RECIPIENTS: u32 = 10;
+pub(crate) const PRICE_DECIMALS: u32 = 7;
+pub(crate) const MIN_FEE_DELAY: u32 = DAY_IN_LEDGERS / 24; // about an hour
+pub(crate) const MAX_PENDING_FEE_CHANGES: u32 = 10; // of each kind of setting
 
 pub(crate) const DAY_IN_LEDGERS: u32 = 17280;
 pub(crate) const INSTANCE_BUMP_AMOUNT: u32 = 7 * DAY_IN_LEDGERS; // 7 days
 pub(crate) const INSTANCE_LIFETIME_THRESHOLD: u32 = INSTANCE_BUMP_AMOUNT - DAY_IN_LEDGERS; // 6 days
 pub(crate) const BALANCE_BUMP_AMOUNT: u32 = 30 * DAY_IN_LEDGERS; // 30 days
+pub(crate) const BALANCE_LIFETIME_THRESHOLD: u32 = BALANCE_BUMP_AMOUNT - DAY_IN_LEDGERS; // 29 days
 
+pub(crate) const MAX_PAGE_SIZE: u32 = 50;
+pub(crate) const MAX_ACCEPTORS: u32 = 10;
+pub(crate) const MAX_FEE_TIERS: u32 = 10;
+pub(crate) const INDEX_PAGE_SIZE: u32 = 50;
 
-#[derive(Clone)]
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 #[contracttype]
 pub struct FeeInfo {
+    // charged to the offeror on send_amount
+    pub maker_fee_rate: u32,
+    // charged to the acceptor on the accepted amount
+    pub taker_fee_rate: u32,
+}
+
+// Fee change taking effect from `effective_ledger` on.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct PendingFee {
+    pub fee_info: FeeInfo,
+    pub effective_ledger: u32,
+}
+
+// Share of the collected fees paid to a wallet, in basis points.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct FeeRecipient {
+    pub wallet: Address,
+    pub share_bps: u32,
+}
+
+// Overrides the global fee rates for a token pair, in either direction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct PairFee {
+    pub maker_fee_rate: u32,
+    pub taker_fee_rate: u32,
+}
+
+// Fee rate applied once an address has traded at least `volume` of a token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct FeeTier {
+    pub volume: u128,
     pub fee_rate: u32,
-    pub fee_wallet: Address,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+// Token in which opted-in accounts pay their fees, at a discount in basis points.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct FeeToken {
+    pub token: Address,
+    pub discount_bps: u32,
+}
+
+// Change of a fee setting, scheduled at least MIN_FEE_DELAY ledgers ahead and kept in the fee history.
+// Pair fees are keyed by their tokens in ascending order, a zero price removes the token's price.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub enum FeeChange {
+    Fee(FeeInfo),
+    PairFee(Address, Address, PairFee),
+    PairFeeClear(Address, Address),
+    Tiers(Address, Vec<FeeTier>),
+    FeeToken(FeeToken),
+    FeeTokenClear,
+    FeeTokenPrice(Address, u128),
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[contracttype]
 pub enum OfferStatus {
     INIT = 0,
     ACTIVE = 1,
     COMPLETE = 2,
-    CANCEL = 3
+    CANCEL = 3,
+    EXPIRED = 4
+}
+
+// How an offer may be filled, MinLot fills must be multiples of the lot size in recv_token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub enum FillPolicy {
+    Partial,
+    AllOrNone,
+    MinLot(u64),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[contracttype]
+pub enum Role {
+    FeeManager = 0,
+    TokenLister = 1,
+    Pauser = 2,
+    Upgrader = 3
 }
 
 // Represents an offer managed by the TokenSwap contract.
@@ -42,20 +132,63 @@ pub struct OfferInfo {
     // offeror-defined amount of the recv token
     pub recv_amount: u64,
     pub min_recv_amount: u64,
+    // ledger timestamp from which the offer can't be accepted anymore, 0 if it never expires
+    pub expiry: u64,
+
+    // token the maker fee was paid in and the part of it not charged yet,
+    // charged pro rata on fills and refunded when the offer is closed
+    pub fee_token: Address,
+    pub fee_escrow: u64,
+    // referrer taking its share of the maker fee as it is charged
+    pub referral: Referral,
+    // the only addresses that may fill the offer, anyone if empty
+    pub acceptors: Vec<Address>,
+    pub fill_policy: FillPolicy,
+    // positions of the offer in the pair and offeror indexes while it's open
+    pub pair_pos: u32,
+    pub offeror_pos: u32,
+    // position of the offer in the offeror history
+    pub history_pos: u32,
 
     pub status: OfferStatus
 }
 
+// Offer as returned to clients, with its current status and without its index positions.
 #[derive(Clone)]
 #[contracttype]
-pub struct OfferKey {
+pub struct OfferView {
     pub offeror: Address,
     pub send_token: Address,
     pub recv_token: Address,
-    pub timestamp: u32,
+    pub send_amount: u64,
+    pub recv_amount: u64,
+    pub min_recv_amount: u64,
+    pub expiry: u64,
+    pub fee_token: Address,
+    pub fee_escrow: u64,
+    pub referral: Referral,
+    pub acceptors: Vec<Address>,
+    pub fill_policy: FillPolicy,
+    pub status: OfferStatus
 }
 
+// Referrer of a trade and its share of the fee in basis points.
+#[derive(Clone)]
+#[contracttype]
+pub enum Referral {
+    None,
+    Referrer(Address, u32),
+}
+
+// Secondary index of the open offers.
+#[derive(Clone)]
+#[contracttype]
+pub enum IndexKey {
+    Pair(Address, Address),
+    Offeror(Address),
+}
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone)]
 #[contracttype]
 pub enum DataKey {
@@ -63,6 +196,27 @@ pub enum DataKey {
     Allowance(Address),
     OfferCount,
     RegOffers(u32),
-    ErrorCode,
+    IndexPage(IndexKey, u32),
+    IndexLen(IndexKey),
+    OfferorHistory(Address, u32),
+    OfferorHistoryLen(Address),
     Admin,
+    PendingAdmin,
+    Role(Role, Address),
+    Paused,
+    TokenPaused(Address),
+    Version,
+    FeeCap,
+    PairFee(Address, Address),
+    FeeTiers(Address),
+    Volume(Address, Address),
+    AccruedFees(Address),
+    FeeRecipients,
+    ReferralFeeCap,
+    FeeToken,
+    FeeTokenPrice(Address),
+    FeeTokenOptIn(Address),
+    PendingFeeChanges,
+    FeeHistory(u32),
+    FeeHistoryLen,
 }
@@ -5,14 +5,17 @@ pub(crate) const DEF_FEE_RATE: u32 = 25;   // default fee_rate is 0.25%
 pub(crate) const TOKEN_DECIMALS: u32 = 4;
 
 
-use soroban_sdk::{ log, token, BytesN };
-use crate::storage_types::{ BALANCE_BUMP_AMOUNT, FeeInfo };
+use soroban_sdk::{ log, token, vec, BytesN, Vec };
+use crate::storage_types::{ BALANCE_BUMP_AMOUNT, MIN_FEE_DELAY, MAX_PENDING_FEE_CHANGES, INDEX_PAGE_SIZE, MAX_FEE_TIERS, MAX_PAGE_SIZE, FeeChange, FeeInfo, FeeRecipient, FeeTier, FeeToken, FillPolicy, OfferStatus, PairFee, PendingFee, Referral, Role };
+use crate::fee::{ calculate_fee };
+use crate::error::SwapError;
+use crate::upgrade::{ version_write };
 use crate::{ TokenSwap, TokenSwapClient };
 
 
 use soroban_sdk::{
     symbol_short, Symbol,
-    testutils::{Address as _, AuthorizedFunction, AuthorizedInvocation},
+    testutils::{Address as _, AuthorizedFunction, AuthorizedInvocation, BytesN as _, Ledger},
     Address, Env, IntoVal,
 };
 
@@ -32,9 +35,38 @@ fn create_token_contract<'a>(
 fn create_token_swap_contract<'a>(
     e: &Env,
 ) -> TokenSwapClient<'a> {
-    let token_swap = TokenSwapClient::new(e, &e.register_contract(None, TokenSwap {}));
+    TokenSwapClient::new(e, &e.register_contract(None, TokenSwap {}))
+}
+
+struct TestSetup<'a> {
+    token_swap: TokenSwapClient<'a>,
+    admin: Address,
+    offeror: Address,
+    acceptor: Address,
+    send_token: token::Client<'a>,
+    recv_token: token::Client<'a>,
+}
+
+// Initialized contract with both tokens allowed, the offeror holding 1000 send_tokens
+// and the acceptor holding 100 recv_tokens.
+fn setup_token_swap<'a>(e: &Env) -> TestSetup<'a> {
+    const MUL_VAL: i128 = i128::pow(10, TOKEN_DECIMALS);
+
+    let token_admin = Address::generate(e);
+    let admin = Address::generate(e);
+    let fee_wallet = Address::generate(e);
+    let offeror = Address::generate(e);
+    let acceptor = Address::generate(e);
 
-    token_swap
+    let (send_token_id, send_token, send_token_admin) = create_token_contract(e, &token_admin);
+    let (recv_token_id, recv_token, recv_token_admin) = create_token_contract(e, &token_admin);
+    send_token_admin.mint(&offeror, &(1000 * MUL_VAL));
+    recv_token_admin.mint(&acceptor, &(100 * MUL_VAL));
+
+    let token_swap = create_token_swap_contract(e);
+    token_swap.initialize(&admin, &DEF_FEE_RATE, &DEF_FEE_RATE, &fee_wallet, &vec![e, send_token_id, recv_token_id]);
+
+    TestSetup { token_swap, admin, offeror, acceptor, send_token, recv_token }
 }
 
 
@@ -44,9 +76,9 @@ fn test() {
     e.mock_all_auths();
 
 
-    let token_admin = Address::random(&e);
-    let offeror = Address::random(&e);
-    let acceptor = Address::random(&e);
+    let token_admin = Address::generate(&e);
+    let offeror = Address::generate(&e);
+    let acceptor = Address::generate(&e);
     const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
     
     
@@ -70,16 +102,21 @@ fn test() {
     recv_token_admin_client.mint(&acceptor.clone(), &(100_i128 * MUL_VAL as i128));
     
     
-    // init fee
+    // init admin and fee
+    let admin = Address::generate(&e);
     let fee_rate = DEF_FEE_RATE;
-    let fee_wallet = Address::random(&e);
+    let fee_wallet = Address::generate(&e);
 
-    token_swap.set_fee(&fee_rate, &fee_wallet);
+    token_swap.initialize(&admin, &fee_rate, &fee_rate, &fee_wallet, &vec![&e]);
+    assert_eq!(token_swap.try_initialize(&admin, &fee_rate, &fee_rate, &fee_wallet, &vec![&e]), 
+        Err(Ok(SwapError::AlreadyInitialized)));
+    assert_eq!(token_swap.get_fee(), (FeeInfo { maker_fee_rate: fee_rate, taker_fee_rate: fee_rate }, None));
     
 
     // allow tokens
-    token_swap.allow_token(&send_token_id);
-    token_swap.allow_token(&recv_token_id);
+    token_swap.allow_token(&admin, &send_token_id);
+    token_swap.allow_token(&admin, &recv_token_id);
+    assert_eq!(token_swap.try_allow_token(&admin, &send_token_id), Err(Ok(SwapError::TokenAlreadyAllowed)));
 
     send_token_client.approve(&offeror.clone(), &token_swap.address.clone(), 
         &((1000 * MUL_VAL) as i128), &(e.ledger().sequence() + BALANCE_BUMP_AMOUNT));
@@ -98,7 +135,9 @@ fn test() {
         &timestamp,
         &(500 * MUL_VAL),
         &(50 * MUL_VAL),
-        &(10 * MUL_VAL));
+        &(10 * MUL_VAL),
+        &vec![&e],                  // anyone may accept
+        &FillPolicy::Partial);
     
     // Verify that authorization is required for the offeror.
     assert_eq!(
@@ -116,7 +155,9 @@ fn test() {
                         timestamp,
                         500 * MUL_VAL,
                         50 * MUL_VAL,
-                        10 * MUL_VAL
+                        10 * MUL_VAL,
+                        Vec::<Address>::new(&e),
+                        FillPolicy::Partial
                     )
                         .into_val(&e)
                 )),
@@ -128,74 +169,72 @@ fn test() {
                             (
                                 offeror.clone(),
                                 token_swap.address.clone(),
-                                (500 * MUL_VAL) as i128,
+                                (500 * MUL_VAL) as i128 + 12500,
                             )
                                 .into_val(&e)
                         )),
                         sub_invocations: std::vec![]
-                    },
-                    AuthorizedInvocation {
-                        function: AuthorizedFunction::Contract((
-                            send_token_id.clone(),
-                            symbol_short!("transfer"),
-                            (
-                                offeror.clone(),
-                                fee_wallet.clone(),
-                                12500_i128,
-                            )
-                                .into_val(&e)
-                        )),
-                        sub_invocations: std::vec![],
                     }
                 ]
             }
         )]
     );
 
-    // trying to create an offer with same params - still success
-    let _ = token_swap.try_create_offer(
+    // trying to create an offer with same params - fails due to insufficient balance
+    let res = token_swap.try_create_offer(
         &offeror,
         &send_token_id,
         &recv_token_id,
         &timestamp,
         &(500 * MUL_VAL),
         &(50 * MUL_VAL),
-        &(10 * MUL_VAL));
+        &(10 * MUL_VAL),
+        &vec![&e],                  // anyone may accept
+        &FillPolicy::Partial);
+    assert_eq!(res, Err(Ok(SwapError::InsufficientBalance)));
 
     // trying to create an offer with different timestamp - fails due to insufficient balance
     let timestamp2: u32 = timestamp + 127;
-    let _ = token_swap.try_create_offer(
+    let res = token_swap.try_create_offer(
         &offeror,
         &send_token_id,
         &recv_token_id,
         &timestamp2,
         &(500 * MUL_VAL),
         &(50 * MUL_VAL),
-        &(10 * MUL_VAL));
+        &(10 * MUL_VAL),
+        &vec![&e],                  // anyone may accept
+        &FillPolicy::Partial);
+    assert_eq!(res, Err(Ok(SwapError::InsufficientBalance)));
     
     
     // Try accepting 9 recv_token for at least 10 recv_token - that wouldn't
     // succeed because minimum recv amount is 10 recv_token.
-    assert!(token_swap.try_accept_offer(
+    assert_eq!(token_swap.try_accept_offer(
         &acceptor, 
         &offer_id, 
-        &(9 * MUL_VAL)).is_err());
+        &(9 * MUL_VAL), 
+        &0, 
+        &0), Err(Ok(SwapError::AmountBelowMinRecvAmount)));
     
     // acceptor accepts 10 recv_tokens.
     token_swap.accept_offer(
         &acceptor,
         &offer_id,
-        &(10 * MUL_VAL));
+        &(10 * MUL_VAL),
+        &0,     // min_send_out
+        &0);    // deadline
     
     assert_eq!(send_token_client.balance(&offeror), (500 * MUL_VAL) as i128 - 12500);
-    assert_eq!(send_token_client.balance(&token_swap.address), (400 * MUL_VAL) as i128);
+    assert_eq!(send_token_client.balance(&token_swap.address), (400 * MUL_VAL) as i128 + 12500);
     assert_eq!(send_token_client.balance(&acceptor), (100 * MUL_VAL) as i128);
-    assert_eq!(send_token_client.balance(&fee_wallet), 12500);
+    assert_eq!(token_swap.accrued_fees(&send_token_id), 2500);
+    assert_eq!(token_swap.load_offer(&offer_id).fee_escrow, 10000);
     
     assert_eq!(recv_token_client.balance(&offeror), (10 * MUL_VAL) as i128);
-    assert_eq!(recv_token_client.balance(&token_swap.address), 0);
+    assert_eq!(recv_token_client.balance(&token_swap.address), 250);
     assert_eq!(recv_token_client.balance(&acceptor), (90 * MUL_VAL) as i128 - 250);
-    assert_eq!(recv_token_client.balance(&fee_wallet), 250);
+    assert_eq!(token_swap.accrued_fees(&recv_token_id), 250);
     
     
     // update (recv_amount, min_recv_amount) from (40, 10) to (80, 20)
@@ -211,37 +250,950 @@ fn test() {
     token_swap.accept_offer(
         &acceptor, 
         &offer_id, 
-        &(40 * MUL_VAL));
+        &(40 * MUL_VAL), 
+        &0, 
+        &0);
     
     assert_eq!(send_token_client.balance(&offeror), (500 * MUL_VAL) as i128 - 12500);
-    assert_eq!(send_token_client.balance(&token_swap.address), (200 * MUL_VAL) as i128);
+    assert_eq!(send_token_client.balance(&token_swap.address), (200 * MUL_VAL) as i128 + 12500);
     assert_eq!(send_token_client.balance(&acceptor), (300 * MUL_VAL) as i128);
-    assert_eq!(send_token_client.balance(&fee_wallet), 12500);
+    assert_eq!(token_swap.accrued_fees(&send_token_id), 7500);
+    assert_eq!(token_swap.load_offer(&offer_id).fee_escrow, 5000);
 
     assert_eq!(recv_token_client.balance(&offeror), (50 * MUL_VAL) as i128);
-    assert_eq!(recv_token_client.balance(&token_swap.address), 0);
+    assert_eq!(recv_token_client.balance(&token_swap.address), 1250);
     assert_eq!(recv_token_client.balance(&acceptor), (50 * MUL_VAL) as i128 - 1250);
-    assert_eq!(recv_token_client.balance(&fee_wallet), 1250);
+    assert_eq!(token_swap.accrued_fees(&recv_token_id), 1250);
     
     
+    // anyone can keep the offer alive
+    token_swap.bump_offer(&offer_id);
+    assert_eq!(token_swap.try_bump_offer(&(offer_id + 1)), Err(Ok(SwapError::OfferNotFound)));
+
+    // only the offeror can close the offer
+    assert_eq!(token_swap.try_close_offer(&acceptor, &offer_id), Err(Ok(SwapError::InvalidOfferor)));
+    assert_eq!(token_swap.try_close_offer(&offeror, &(offer_id + 1)), Err(Ok(SwapError::OfferNotFound)));
+
     // offeror closes offer
     token_swap.close_offer(
         &offeror,
         &offer_id
     );
 
-    assert_eq!(send_token_client.balance(&offeror), (700 * MUL_VAL) as i128 - 12500);
-    assert_eq!(send_token_client.balance(&token_swap.address), 0);
+    // the unused maker fee is refunded
+    assert_eq!(send_token_client.balance(&offeror), (700 * MUL_VAL) as i128 - 7500);
+    assert_eq!(send_token_client.balance(&token_swap.address), 7500);
+    assert_eq!(token_swap.load_offer(&offer_id).fee_escrow, 0);
     assert_eq!(send_token_client.balance(&acceptor), (300 * MUL_VAL) as i128);
-    assert_eq!(send_token_client.balance(&fee_wallet), 12500);
+
+    // a closed offer is no longer indexed but can still be kept alive
+    token_swap.bump_offer(&offer_id);
     
     assert_eq!(recv_token_client.balance(&offeror), (50 * MUL_VAL) as i128);
-    assert_eq!(recv_token_client.balance(&token_swap.address), 0);
+    assert_eq!(recv_token_client.balance(&token_swap.address), 1250);
     assert_eq!(recv_token_client.balance(&acceptor), (50 * MUL_VAL) as i128 - 1250);
+
+
+    // fee manager withdraws the accrued fees
+    assert_eq!(token_swap.try_withdraw_fees(&offeror, &send_token_id, &7500), 
+        Err(Ok(SwapError::MissingRole)));
+    assert_eq!(token_swap.try_withdraw_fees(&admin, &send_token_id, &7501), 
+        Err(Ok(SwapError::InsufficientFees)));
+    token_swap.withdraw_fees(&admin, &send_token_id, &5000);
+    token_swap.withdraw_fees(&admin, &send_token_id, &2500);
+    token_swap.withdraw_fees(&admin, &recv_token_id, &1250);
+
+    assert_eq!(send_token_client.balance(&token_swap.address), 0);
+    assert_eq!(send_token_client.balance(&fee_wallet), 7500);
+    assert_eq!(token_swap.accrued_fees(&send_token_id), 0);
+    assert_eq!(recv_token_client.balance(&token_swap.address), 0);
     assert_eq!(recv_token_client.balance(&fee_wallet), 1250);
+    assert_eq!(token_swap.accrued_fees(&recv_token_id), 0);
+
+
+    // closed offer can't be accepted or updated
+    assert_eq!(token_swap.try_accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0), Err(Ok(SwapError::OfferNotActive)));
+    assert_eq!(token_swap.try_update_offer(&offeror, &offer_id, &(10 * MUL_VAL), &(10 * MUL_VAL)), Err(Ok(SwapError::OfferNotActive)));
 
 
     // disallow tokens
-    token_swap.disallow_token(&send_token_id);
-    token_swap.disallow_token(&recv_token_id);
+    token_swap.disallow_token(&admin, &send_token_id);
+    token_swap.disallow_token(&admin, &recv_token_id);
+    assert_eq!(token_swap.try_disallow_token(&admin, &send_token_id), Err(Ok(SwapError::TokenNotAllowed)));
+}
+
+
+#[test]
+fn test_offer_expiry() {
+    let e = Env::default();
+    e.mock_all_auths();
+    e.ledger().with_mut(|li| li.timestamp = 1000);
+
+    let TestSetup { token_swap, offeror, acceptor, send_token, recv_token, .. } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+
+    // expiry must be in the future
+    assert_eq!(token_swap.try_create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &1000, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial), 
+        Err(Ok(SwapError::InvalidExpiry)));
+
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &2000, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+    assert_eq!(token_swap.try_reclaim_expired(&offer_id), Err(Ok(SwapError::OfferNotExpired)));
+
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.load_offer(&offer_id).status, OfferStatus::ACTIVE);
+
+    // offer can't be filled once expired
+    e.ledger().with_mut(|li| li.timestamp = 2000);
+    assert_eq!(token_swap.load_offer(&offer_id).status, OfferStatus::EXPIRED);
+    assert_eq!(token_swap.get_offers_of(&offeror, &0, &10), (vec![&e, (offer_id, OfferStatus::EXPIRED)], None));
+    assert_eq!(token_swap.try_accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0), 
+        Err(Ok(SwapError::OfferExpired)));
+    assert_eq!(token_swap.try_update_offer(&offeror, &offer_id, &(40 * MUL_VAL), &(10 * MUL_VAL)), 
+        Err(Ok(SwapError::OfferExpired)));
+
+    // anyone returns the remaining escrow to the offeror
+    token_swap.reclaim_expired(&offer_id);
+    assert_eq!(send_token.balance(&offeror), (900 * MUL_VAL) as i128 - 2500);
+    assert_eq!(send_token.balance(&token_swap.address), 2500);
+    assert_eq!(token_swap.load_offer(&offer_id).status, OfferStatus::EXPIRED);
+    assert_eq!(token_swap.try_reclaim_expired(&offer_id), Err(Ok(SwapError::OfferNotActive)));
+    assert_eq!(token_swap.try_close_offer(&offeror, &offer_id), Err(Ok(SwapError::OfferNotActive)));
+}
+
+
+#[test]
+fn test_list_offers() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, offeror, acceptor, send_token, recv_token, .. } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+
+    // offeror sells send_token in 3 offers, acceptor sells recv_token in 1 offer
+    for _ in 0..3 {
+        token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+            &0, &(100 * MUL_VAL), &(10 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+    }
+    let reverse_id = token_swap.create_offer(&acceptor, &recv_token.address, &send_token.address, 
+        &0, &(10 * MUL_VAL), &(100 * MUL_VAL), &(100 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+
+    // offer 0 is completed and offer 1 is cancelled
+    token_swap.accept_offer(&acceptor, &0, &(10 * MUL_VAL), &0, &0);
+    token_swap.close_offer(&offeror, &1);
+
+    let (offers, next) = token_swap.list_offers(&0, &10, &None);
+    assert_eq!(offers.len(), 4);
+    assert_eq!(offers.get_unchecked(1).1.status, OfferStatus::CANCEL);
+    assert_eq!(next, None);
+
+    let (offers, _) = token_swap.list_offers(&0, &10, &Some(OfferStatus::ACTIVE));
+    assert_eq!(offers.len(), 2);
+    assert_eq!(offers.get_unchecked(0).0, 2);
+    assert_eq!(offers.get_unchecked(1).0, reverse_id);
+
+    // the scan stops after `limit` ids even if none matched
+    let (offers, next) = token_swap.list_offers(&0, &2, &Some(OfferStatus::ACTIVE));
+    assert_eq!(offers.len(), 0);
+    assert_eq!(next, Some(2));
+
+    let (offers, next) = token_swap.list_offers(&1, &1, &None);
+    assert_eq!(offers.len(), 1);
+    assert_eq!(offers.get_unchecked(0).0, 1);
+    assert_eq!(next, Some(2));
+
+    // an empty page doesn't hand out a cursor to loop on
+    let (offers, next) = token_swap.list_offers(&1, &0, &None);
+    assert_eq!((offers.len(), next), (0, None));
+    assert_eq!(token_swap.list_offers_by_offeror(&offeror, &0, &0).1, None);
+
+    // only open offers are indexed
+    let (offers, next) = token_swap.list_offers_by_pair(&send_token.address, &recv_token.address, &0, &10);
+    assert_eq!(offers.len(), 1);
+    assert_eq!(offers.get_unchecked(0).0, 2);
+    assert_eq!(next, None);
+
+    let (offers, _) = token_swap.list_offers_by_pair(&recv_token.address, &send_token.address, &0, &10);
+    assert_eq!(offers.len(), 1);
+    assert_eq!(offers.get_unchecked(0).0, reverse_id);
+    assert_eq!(offers.get_unchecked(0).1.offeror, acceptor);
+
+    let (offers, _) = token_swap.list_offers_by_offeror(&offeror, &0, &10);
+    assert_eq!(offers.len(), 1);
+    assert_eq!(offers.get_unchecked(0).0, 2);
+    assert_eq!(token_swap.list_offers_by_offeror(&offeror, &1, &10).0.len(), 0);
+
+    token_swap.close_offer(&offeror, &2);
+    assert_eq!(token_swap.list_offers_by_offeror(&offeror, &0, &10).0.len(), 0);
+
+    // history keeps every offer of the offeror
+    assert_eq!(token_swap.get_offers_of(&offeror, &0, &10), (vec![&e, 
+        (0, OfferStatus::COMPLETE), (1, OfferStatus::CANCEL), (2, OfferStatus::CANCEL)], None));
+    assert_eq!(token_swap.get_offers_of(&offeror, &1, &1), (vec![&e, (1, OfferStatus::CANCEL)], Some(2)));
+    assert_eq!(token_swap.get_offers_of(&acceptor, &0, &10), (vec![&e, (reverse_id, OfferStatus::ACTIVE)], None));
+}
+
+
+#[test]
+fn test_index_pages() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, offeror, send_token, recv_token, .. } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+
+    // the pair index spans two pages
+    let count = INDEX_PAGE_SIZE + 3;
+    for _ in 0..count {
+        token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+            &0, &MUL_VAL, &MUL_VAL, &MUL_VAL, &vec![&e], &FillPolicy::Partial);
+    }
+
+    // the last offer takes the place of the removed one
+    token_swap.close_offer(&offeror, &0);
+    token_swap.close_offer(&offeror, &INDEX_PAGE_SIZE);
+
+    let (offers, next) = token_swap.list_offers_by_pair(&send_token.address, &recv_token.address, &0, &MAX_PAGE_SIZE);
+    assert_eq!(offers.len(), MAX_PAGE_SIZE);
+    assert_eq!(offers.get_unchecked(0).0, count - 1);
+    assert_eq!(next, Some(MAX_PAGE_SIZE));
+
+    let (offers, next) = token_swap.list_offers_by_pair(&send_token.address, &recv_token.address, &MAX_PAGE_SIZE, &MAX_PAGE_SIZE);
+    assert_eq!(offers.len(), 1);
+    assert_eq!(offers.get_unchecked(0).0, INDEX_PAGE_SIZE + 1);
+    assert_eq!(next, None);
+
+    // moved offers can be removed too
+    token_swap.close_offer(&offeror, &(count - 1));
+    let (offers, next) = token_swap.list_offers_by_offeror(&offeror, &0, &MAX_PAGE_SIZE);
+    assert_eq!(offers.len(), INDEX_PAGE_SIZE);
+    assert_eq!(offers.get_unchecked(0).0, INDEX_PAGE_SIZE + 1);
+    assert_eq!(next, None);
+    assert!(offers.iter().all(|(offer_id, offer)| offer_id != 0 && offer_id != INDEX_PAGE_SIZE && offer.status == OfferStatus::ACTIVE));
+
+    // the history spans two pages as well
+    let (history, next) = token_swap.get_offers_of(&offeror, &(INDEX_PAGE_SIZE - 1), &MAX_PAGE_SIZE);
+    assert_eq!(history, vec![&e, (INDEX_PAGE_SIZE - 1, OfferStatus::ACTIVE), (INDEX_PAGE_SIZE, OfferStatus::CANCEL), 
+        (INDEX_PAGE_SIZE + 1, OfferStatus::ACTIVE), (count - 1, OfferStatus::CANCEL)]);
+    assert_eq!(next, None);
+}
+
+#[test]
+fn test_admin_transfer() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, admin, .. } = setup_token_swap(&e);
+    let new_admin = Address::generate(&e);
+
+    assert_eq!(token_swap.get_pending_admin(), None);
+    assert_eq!(token_swap.try_accept_admin(), Err(Ok(SwapError::NoPendingAdmin)));
+    assert_eq!(token_swap.try_cancel_admin_proposal(), Err(Ok(SwapError::NoPendingAdmin)));
+
+    // a proposal can be cancelled by the admin
+    token_swap.propose_admin(&new_admin);
+    assert_eq!(token_swap.get_pending_admin(), Some(new_admin.clone()));
+    token_swap.cancel_admin_proposal();
+    assert_eq!(e.auths()[0].0, admin);
+    assert_eq!(token_swap.get_pending_admin(), None);
+
+    // the new admin takes over only after accepting
+    token_swap.propose_admin(&new_admin);
+    assert_eq!(e.auths()[0].0, admin);
+    token_swap.accept_admin();
+    assert_eq!(
+        e.auths(),
+        std::vec![(
+            new_admin.clone(),
+            AuthorizedInvocation {
+                function: AuthorizedFunction::Contract((
+                    token_swap.address.clone(),
+                    Symbol::new(&e, "accept_admin"),
+                    ().into_val(&e)
+                )),
+                sub_invocations: std::vec![]
+            }
+        )]
+    );
+    assert_eq!(token_swap.get_pending_admin(), None);
+
+    // the roles of the previous admin move to the new admin
+    for role in [Role::FeeManager, Role::TokenLister, Role::Pauser, Role::Upgrader] {
+        assert!(!token_swap.has_role(&role, &admin));
+        assert!(token_swap.has_role(&role, &new_admin));
+    }
+    assert_eq!(token_swap.try_set_fee(&admin, &DEF_FEE_RATE, &DEF_FEE_RATE, &(e.ledger().sequence() + MIN_FEE_DELAY)), Err(Ok(SwapError::MissingRole)));
+    token_swap.set_fee(&new_admin, &DEF_FEE_RATE, &DEF_FEE_RATE, &(e.ledger().sequence() + MIN_FEE_DELAY));
+    assert_eq!(e.auths()[0].0, new_admin);
+
+    // roles granted to others stay with their holders
+    let pauser = Address::generate(&e);
+    token_swap.grant_role(&Role::Pauser, &pauser);
+    token_swap.propose_admin(&admin);
+    token_swap.accept_admin();
+    assert!(token_swap.has_role(&Role::Pauser, &pauser));
+    assert!(token_swap.has_role(&Role::Upgrader, &admin));
+    assert!(!token_swap.has_role(&Role::Upgrader, &new_admin));
+}
+
+
+#[test]
+fn test_roles() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, admin, send_token, .. } = setup_token_swap(&e);
+    let fee_manager = Address::generate(&e);
+    let token_lister = Address::generate(&e);
+
+    // admin holds every role after initialize
+    assert!(token_swap.has_role(&Role::FeeManager, &admin));
+    assert!(token_swap.has_role(&Role::Upgrader, &admin));
+
+    token_swap.grant_role(&Role::FeeManager, &fee_manager);
+    token_swap.grant_role(&Role::TokenLister, &token_lister);
+    assert_eq!(e.auths()[0].0, admin);
+    assert!(token_swap.has_role(&Role::TokenLister, &token_lister));
+    assert!(!token_swap.has_role(&Role::FeeManager, &token_lister));
+
+    // each role only unlocks its own entry points
+    token_swap.set_fee(&fee_manager, &DEF_FEE_RATE, &DEF_FEE_RATE, &(e.ledger().sequence() + MIN_FEE_DELAY));
+    assert_eq!(e.auths()[0].0, fee_manager);
+    assert_eq!(token_swap.try_disallow_token(&fee_manager, &send_token.address), Err(Ok(SwapError::MissingRole)));
+
+    token_swap.disallow_token(&token_lister, &send_token.address);
+    assert_eq!(e.auths()[0].0, token_lister);
+    assert_eq!(token_swap.try_set_fee(&token_lister, &DEF_FEE_RATE, &DEF_FEE_RATE, &(e.ledger().sequence() + MIN_FEE_DELAY)), Err(Ok(SwapError::MissingRole)));
+
+    token_swap.revoke_role(&Role::TokenLister, &token_lister);
+    assert!(!token_swap.has_role(&Role::TokenLister, &token_lister));
+    assert_eq!(token_swap.try_allow_token(&token_lister, &send_token.address), Err(Ok(SwapError::MissingRole)));
+    assert_eq!(token_swap.try_revoke_role(&Role::TokenLister, &token_lister), Err(Ok(SwapError::MissingRole)));
+}
+
+
+#[test]
+fn test_pause() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, admin, offeror, acceptor, send_token, recv_token } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+
+    // only pausers can pause
+    assert_eq!(token_swap.try_pause(&offeror), Err(Ok(SwapError::MissingRole)));
+
+    token_swap.pause(&admin);
+    assert!(token_swap.is_paused());
+    assert_eq!(token_swap.try_create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(100 * MUL_VAL), &(10 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial), Err(Ok(SwapError::ContractPaused)));
+    assert_eq!(token_swap.try_accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0), 
+        Err(Ok(SwapError::ContractPaused)));
+    token_swap.unpause(&admin);
+    assert!(!token_swap.is_paused());
+
+    token_swap.pause_token(&admin, &recv_token.address);
+    assert!(token_swap.is_token_paused(&recv_token.address));
+    assert!(!token_swap.is_token_paused(&send_token.address));
+    assert_eq!(token_swap.try_accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0), 
+        Err(Ok(SwapError::TokenPaused)));
+
+    // offeror can always withdraw
+    token_swap.pause(&admin);
+    token_swap.close_offer(&offeror, &offer_id);
+    assert_eq!(send_token.balance(&offeror), (1000 * MUL_VAL) as i128);
+
+    token_swap.unpause(&admin);
+    token_swap.unpause_token(&admin, &recv_token.address);
+    assert!(!token_swap.is_token_paused(&recv_token.address));
+}
+
+
+#[test]
+fn test_upgrade() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, admin, offeror, acceptor, send_token, recv_token } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(10 * MUL_VAL), &(10 * MUL_VAL), &MUL_VAL, &vec![&e], &FillPolicy::Partial);
+
+    assert_eq!(token_swap.version(), 1);
+    assert_eq!(token_swap.try_migrate(&admin), Err(Ok(SwapError::AlreadyMigrated)));
+
+    // only upgraders can upgrade or migrate
+    let new_wasm_hash = BytesN::<32>::random(&e);
+    assert_eq!(token_swap.try_upgrade(&offeror, &new_wasm_hash), Err(Ok(SwapError::MissingRole)));
+    assert_eq!(token_swap.try_migrate(&offeror), Err(Ok(SwapError::MissingRole)));
+
+    // nothing trades on an older storage layout until it's migrated
+    e.as_contract(&token_swap.address, || version_write(&e, 0));
+    assert_eq!(token_swap.try_create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &MUL_VAL, &MUL_VAL, &MUL_VAL, &vec![&e], &FillPolicy::Partial), Err(Ok(SwapError::NotMigrated)));
+    assert_eq!(token_swap.try_accept_offer(&acceptor, &offer_id, &MUL_VAL, &0, &0), Err(Ok(SwapError::NotMigrated)));
+    assert_eq!(token_swap.try_set_fee(&admin, &DEF_FEE_RATE, &DEF_FEE_RATE, &(e.ledger().sequence() + MIN_FEE_DELAY)), 
+        Err(Ok(SwapError::NotMigrated)));
+
+    token_swap.migrate(&admin);
+    assert_eq!(token_swap.version(), 1);
+    token_swap.accept_offer(&acceptor, &offer_id, &MUL_VAL, &0, &0);
+}
+
+
+#[test]
+fn test_uninitialized() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let token_swap = create_token_swap_contract(&e);
+    let admin = Address::generate(&e);
+    let offeror = Address::generate(&e);
+    let token = Address::generate(&e);
+
+    assert_eq!(token_swap.try_set_fee(&admin, &DEF_FEE_RATE, &DEF_FEE_RATE, &(e.ledger().sequence() + MIN_FEE_DELAY)), Err(Ok(SwapError::NotInitialized)));
+    assert_eq!(token_swap.try_allow_token(&admin, &token), Err(Ok(SwapError::NotInitialized)));
+    assert_eq!(token_swap.try_pause(&admin), Err(Ok(SwapError::NotInitialized)));
+    assert_eq!(token_swap.try_propose_admin(&admin), Err(Ok(SwapError::NotInitialized)));
+    assert_eq!(token_swap.try_create_offer(&offeror, &token, &token, &0, &100, &10, &10, &vec![&e], &FillPolicy::Partial), 
+        Err(Ok(SwapError::NotInitialized)));
+    assert_eq!(token_swap.try_accept_offer(&offeror, &0, &10, &0, &0), Err(Ok(SwapError::NotInitialized)));
+    assert_eq!(token_swap.try_update_offer(&offeror, &0, &10, &10), Err(Ok(SwapError::NotInitialized)));
+    assert_eq!(token_swap.try_close_offer(&offeror, &0), Err(Ok(SwapError::NotInitialized)));
+}
+
+
+#[test]
+fn test_fee_cap() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, admin, .. } = setup_token_swap(&e);
+    let fee_wallet = Address::generate(&e);
+
+    // rates above the hard-coded cap are rejected
+    assert_eq!(token_swap.get_fee_cap(), 1000);
+    assert_eq!(token_swap.try_set_fee(&admin, &1001, &1001, &(e.ledger().sequence() + MIN_FEE_DELAY)), Err(Ok(SwapError::InvalidFeeRate)));
+    assert_eq!(token_swap.try_set_fee(&admin, &10000, &10000, &(e.ledger().sequence() + MIN_FEE_DELAY)), Err(Ok(SwapError::InvalidFeeRate)));
+    token_swap.set_fee(&admin, &1000, &1000, &(e.ledger().sequence() + MIN_FEE_DELAY));
+    token_swap.set_fee(&admin, &DEF_FEE_RATE, &DEF_FEE_RATE, &(e.ledger().sequence() + MIN_FEE_DELAY));
+
+    // the cap can only be lowered and never below the current rate
+    assert_eq!(token_swap.try_set_fee_cap(&1001), Err(Ok(SwapError::InvalidFeeCap)));
+    assert_eq!(token_swap.try_set_fee_cap(&(DEF_FEE_RATE - 1)), Err(Ok(SwapError::InvalidFeeRate)));
+    token_swap.set_fee_cap(&100);
+    assert_eq!(e.auths()[0].0, admin);
+    assert_eq!(token_swap.get_fee_cap(), 100);
+    assert_eq!(token_swap.try_set_fee_cap(&1000), Err(Ok(SwapError::InvalidFeeCap)));
+    assert_eq!(token_swap.try_set_fee(&admin, &101, &101, &(e.ledger().sequence() + MIN_FEE_DELAY)), Err(Ok(SwapError::InvalidFeeRate)));
+
+    // large amounts don't overflow
+    assert_eq!(calculate_fee(&e, 1000, u64::MAX), u64::MAX / 10);
+
+    // initialize is bound by the same cap
+    let token_swap = create_token_swap_contract(&e);
+    assert_eq!(token_swap.try_initialize(&admin, &1001, &1001, &fee_wallet, &vec![&e]), 
+        Err(Ok(SwapError::InvalidFeeRate)));
+}
+
+
+#[test]
+fn test_maker_taker_fee() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, admin, offeror, acceptor, send_token, recv_token } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+
+    // no maker fee, 0.5% taker fee
+    token_swap.set_fee(&admin, &0, &50, &(e.ledger().sequence() + MIN_FEE_DELAY));
+    e.ledger().with_mut(|li| li.sequence_number += MIN_FEE_DELAY);
+    assert_eq!(token_swap.get_fee(), (FeeInfo { maker_fee_rate: 0, taker_fee_rate: 50 }, None));
+    assert_eq!(token_swap.try_set_fee(&admin, &0, &1001, &(e.ledger().sequence() + MIN_FEE_DELAY)), Err(Ok(SwapError::InvalidFeeRate)));
+
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+    assert_eq!(send_token.balance(&offeror), (500 * MUL_VAL) as i128);
+    assert_eq!(token_swap.accrued_fees(&send_token.address), 0);
+
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(recv_token.balance(&acceptor), (90 * MUL_VAL) as i128 - 500);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 500);
+}
+
+
+#[test]
+fn test_pair_fee() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, admin, offeror, acceptor, send_token, recv_token } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+
+    assert_eq!(token_swap.get_pair_fee(&send_token.address, &recv_token.address), None);
+    assert_eq!(token_swap.try_set_pair_fee(&offeror, &send_token.address, &recv_token.address, &5, &5, &(e.ledger().sequence() + MIN_FEE_DELAY)), 
+        Err(Ok(SwapError::MissingRole)));
+    assert_eq!(token_swap.try_set_pair_fee(&admin, &send_token.address, &recv_token.address, &1001, &5, &(e.ledger().sequence() + MIN_FEE_DELAY)), 
+        Err(Ok(SwapError::InvalidFeeRate)));
+    assert_eq!(token_swap.try_set_pair_fee(&admin, &send_token.address, &recv_token.address, &10, &20, &e.ledger().sequence()), 
+        Err(Ok(SwapError::InvalidFeeSchedule)));
+
+    // the override applies in both directions once in effect
+    token_swap.set_pair_fee(&admin, &send_token.address, &recv_token.address, &10, &20, &(e.ledger().sequence() + MIN_FEE_DELAY));
+    assert_eq!(token_swap.get_pair_fee(&recv_token.address, &send_token.address), None);
+    e.ledger().with_mut(|li| li.sequence_number += MIN_FEE_DELAY);
+    assert_eq!(token_swap.get_pair_fee(&recv_token.address, &send_token.address), Some((10, 20)));
+
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+    assert_eq!(token_swap.load_offer(&offer_id).fee_escrow, 5000);
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.accrued_fees(&send_token.address), 1000);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 200);
+
+    // falls back to the global rates once cleared
+    token_swap.clear_pair_fee(&admin, &recv_token.address, &send_token.address, &(e.ledger().sequence() + MIN_FEE_DELAY));
+    e.ledger().with_mut(|li| li.sequence_number += MIN_FEE_DELAY);
+    assert_eq!(token_swap.get_pair_fee(&send_token.address, &recv_token.address), None);
+    assert_eq!(token_swap.try_clear_pair_fee(&admin, &send_token.address, &recv_token.address, &(e.ledger().sequence() + MIN_FEE_DELAY)), 
+        Err(Ok(SwapError::PairFeeNotSet)));
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 200 + 250);
+}
+
+
+#[test]
+fn test_fee_tiers() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, admin, offeror, acceptor, send_token, recv_token } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+
+    // tiers must be increasing, below the fee cap and at most MAX_FEE_TIERS
+    let bad_tiers = vec![&e, 
+        FeeTier { volume: (20 * MUL_VAL) as u128, fee_rate: 10 }, 
+        FeeTier { volume: (10 * MUL_VAL) as u128, fee_rate: 5 }];
+    assert_eq!(token_swap.try_set_fee_tiers(&admin, &recv_token.address, &bad_tiers, &(e.ledger().sequence() + MIN_FEE_DELAY)), 
+        Err(Ok(SwapError::InvalidFeeTiers)));
+    assert_eq!(token_swap.try_set_fee_tiers(&admin, &recv_token.address, 
+        &vec![&e, FeeTier { volume: 0, fee_rate: 1001 }], &(e.ledger().sequence() + MIN_FEE_DELAY)), Err(Ok(SwapError::InvalidFeeTiers)));
+    let mut long_tiers = vec![&e];
+    for i in 0..=MAX_FEE_TIERS {
+        long_tiers.push_back(FeeTier { volume: i as u128, fee_rate: 10 });
+    }
+    assert_eq!(token_swap.try_set_fee_tiers(&admin, &recv_token.address, &long_tiers, &(e.ledger().sequence() + MIN_FEE_DELAY)), 
+        Err(Ok(SwapError::InvalidFeeTiers)));
+
+    let tiers = vec![&e, 
+        FeeTier { volume: (10 * MUL_VAL) as u128, fee_rate: 10 }, 
+        FeeTier { volume: (30 * MUL_VAL) as u128, fee_rate: 0 }];
+    token_swap.set_fee_tiers(&admin, &recv_token.address, &tiers, &(e.ledger().sequence() + MIN_FEE_DELAY));
+    e.ledger().with_mut(|li| li.sequence_number += MIN_FEE_DELAY);
+    assert_eq!(token_swap.get_fee_tiers(&recv_token.address), tiers);
+    assert_eq!(token_swap.get_fee_for(&acceptor, &send_token.address, &recv_token.address), (DEF_FEE_RATE, DEF_FEE_RATE));
+
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+
+    // offerors can't fill their own offers to climb the tiers
+    assert_eq!(token_swap.try_accept_offer(&offeror, &offer_id, &(10 * MUL_VAL), &0, &0), 
+        Err(Ok(SwapError::InvalidAcceptor)));
+    assert_eq!(token_swap.get_volume(&offeror, &send_token.address), 0);
+
+    // first fill pays the full rate
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 250);
+    assert_eq!(token_swap.get_volume(&acceptor, &recv_token.address), (10 * MUL_VAL) as u128);
+    assert_eq!(token_swap.get_volume(&offeror, &send_token.address), (100 * MUL_VAL) as u128);
+    assert_eq!(token_swap.get_fee_for(&acceptor, &send_token.address, &recv_token.address), (DEF_FEE_RATE, 10));
+
+    // second fill pays the first tier, third fill pays nothing
+    token_swap.accept_offer(&acceptor, &offer_id, &(20 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 250 + 200);
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 250 + 200);
+    assert_eq!(token_swap.get_fee_for(&acceptor, &send_token.address, &recv_token.address), (DEF_FEE_RATE, 0));
+
+    // the maker rate is tiered on the send token, the taker rate on the recv token
+    assert_eq!(token_swap.get_fee_for(&acceptor, &recv_token.address, &send_token.address), (0, DEF_FEE_RATE));
+
+    // tiers apply on top of a pair override
+    token_swap.set_pair_fee(&admin, &send_token.address, &recv_token.address, &50, &5, &(e.ledger().sequence() + MIN_FEE_DELAY));
+    e.ledger().with_mut(|li| li.sequence_number += MIN_FEE_DELAY);
+    assert_eq!(token_swap.get_fee_for(&offeror, &send_token.address, &recv_token.address), (50, 5));
+    assert_eq!(token_swap.get_fee_for(&acceptor, &send_token.address, &recv_token.address), (50, 0));
+    assert_eq!(token_swap.get_fee_for(&acceptor, &recv_token.address, &send_token.address), (0, 5));
+}
+
+
+#[test]
+fn test_fee_recipients() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, admin, offeror, acceptor, send_token, recv_token } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+    let treasury = Address::generate(&e);
+    let partner = Address::generate(&e);
+
+    // shares must be positive and sum up to 100%
+    assert_eq!(token_swap.get_fee_recipients().len(), 1);
+    assert_eq!(token_swap.try_set_fee_recipients(&admin, &vec![&e]), Err(Ok(SwapError::InvalidFeeRecipients)));
+    assert_eq!(token_swap.try_set_fee_recipients(&admin, &vec![&e, 
+        FeeRecipient { wallet: treasury.clone(), share_bps: 7000 }, 
+        FeeRecipient { wallet: partner.clone(), share_bps: 2000 }]), Err(Ok(SwapError::InvalidFeeRecipients)));
+    assert_eq!(token_swap.try_set_fee_recipients(&admin, &vec![&e, 
+        FeeRecipient { wallet: treasury.clone(), share_bps: 10000 }, 
+        FeeRecipient { wallet: partner.clone(), share_bps: 0 }]), Err(Ok(SwapError::InvalidFeeRecipients)));
+
+    let recipients = vec![&e, 
+        FeeRecipient { wallet: treasury.clone(), share_bps: 7000 }, 
+        FeeRecipient { wallet: partner.clone(), share_bps: 3000 }];
+    assert_eq!(token_swap.try_set_fee_recipients(&offeror, &recipients), Err(Ok(SwapError::MissingRole)));
+    token_swap.set_fee_recipients(&admin, &recipients);
+    assert_eq!(token_swap.get_fee_recipients(), recipients);
+
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 250);
+
+    // the rounding remainder goes to the last recipient
+    token_swap.withdraw_fees(&admin, &recv_token.address, &101);
+    assert_eq!(recv_token.balance(&treasury), 70);
+    assert_eq!(recv_token.balance(&partner), 31);
+    token_swap.withdraw_fees(&admin, &recv_token.address, &149);
+    assert_eq!(recv_token.balance(&treasury), 70 + 104);
+    assert_eq!(recv_token.balance(&partner), 31 + 45);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 0);
+}
+
+
+#[test]
+fn test_referral_fee() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, admin, offeror, acceptor, send_token, recv_token } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+    let referrer = Address::generate(&e);
+
+    // referrals are off until the admin sets a cap
+    assert_eq!(token_swap.get_referral_fee_cap(), 0);
+    assert_eq!(token_swap.try_create_offer_with_referrer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial, &Referral::Referrer(referrer.clone(), 1000)), 
+        Err(Ok(SwapError::InvalidReferralFee)));
+    assert_eq!(token_swap.try_set_referral_fee_cap(&10001), Err(Ok(SwapError::InvalidReferralFee)));
+    token_swap.set_referral_fee_cap(&2000);
+    assert_eq!(e.auths()[0].0, admin);
+    assert_eq!(token_swap.get_referral_fee_cap(), 2000);
+
+    // the referrer takes its share of the maker fee in the send token as it is charged
+    let offer_id = token_swap.create_offer_with_referrer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial, &Referral::Referrer(referrer.clone(), 2000));
+    assert_eq!(send_token.balance(&referrer), 0);
+
+    // and of the taker fee in the recv token
+    assert_eq!(token_swap.try_accept_offer_with_referrer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0, &Referral::Referrer(referrer.clone(), 2001)), 
+        Err(Ok(SwapError::InvalidReferralFee)));
+    token_swap.accept_offer_with_referrer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0, &Referral::Referrer(referrer.clone(), 1000));
+    assert_eq!(send_token.balance(&referrer), 500);
+    assert_eq!(token_swap.accrued_fees(&send_token.address), 2500 - 500);
+    assert_eq!(recv_token.balance(&referrer), 25);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 250 - 25);
+
+    // no taker referrer, no taker share
+    token_swap.accept_offer_with_referrer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0, &Referral::None);
+    assert_eq!(send_token.balance(&referrer), 500 + 500);
+    assert_eq!(recv_token.balance(&referrer), 25);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 250 - 25 + 250);
+}
+
+
+#[test]
+fn test_fee_token() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, admin, offeror, acceptor, send_token, recv_token } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+    let (fee_token_id, fee_token, fee_token_admin) = create_token_contract(&e, &admin);
+    fee_token_admin.mint(&offeror, &((10 * MUL_VAL) as i128));
+    fee_token_admin.mint(&acceptor, &((10 * MUL_VAL) as i128));
+
+    // half price fees in the fee token
+    let config = FeeToken { token: fee_token_id.clone(), discount_bps: 5000 };
+    assert_eq!(token_swap.try_set_fee_token(&offeror, &Some(config.clone()), &(e.ledger().sequence() + MIN_FEE_DELAY)), Err(Ok(SwapError::MissingRole)));
+    assert_eq!(token_swap.try_set_fee_token(&admin, &Some(FeeToken { token: fee_token_id.clone(), discount_bps: 10001 }), &(e.ledger().sequence() + MIN_FEE_DELAY)), 
+        Err(Ok(SwapError::InvalidFeeToken)));
+    token_swap.set_fee_token(&admin, &Some(config.clone()), &(e.ledger().sequence() + MIN_FEE_DELAY));
+    assert_eq!(token_swap.get_fee_token(), None);
+
+    // 1 send token is worth 2 fee tokens
+    token_swap.set_fee_token_price(&admin, &send_token.address, &20_000_000, &(e.ledger().sequence() + MIN_FEE_DELAY));
+    e.ledger().with_mut(|li| li.sequence_number += MIN_FEE_DELAY);
+    assert_eq!(token_swap.get_fee_token(), Some(config));
+    assert_eq!(token_swap.get_fee_token_price(&send_token.address), Some(20_000_000));
+
+    token_swap.pay_fees_in_fee_token(&offeror, &true);
+    assert!(token_swap.pays_fees_in_fee_token(&offeror));
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+    assert_eq!(send_token.balance(&offeror), (500 * MUL_VAL) as i128);
+    assert_eq!(fee_token.balance(&offeror), (10 * MUL_VAL - 12500) as i128);
+    assert_eq!(token_swap.load_offer(&offer_id).fee_escrow, 12500);
+
+    // without a price the fee falls back to the traded token
+    token_swap.pay_fees_in_fee_token(&acceptor, &true);
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 250);
+    assert_eq!(token_swap.accrued_fees(&fee_token_id), 2500);
+    assert_eq!(token_swap.accrued_fees(&send_token.address), 0);
+
+    // 1 recv token is worth half a fee token
+    token_swap.set_fee_token_price(&admin, &recv_token.address, &5_000_000, &(e.ledger().sequence() + MIN_FEE_DELAY));
+    e.ledger().with_mut(|li| li.sequence_number += MIN_FEE_DELAY);
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(recv_token.balance(&acceptor), (80 * MUL_VAL) as i128 - 250);
+    assert_eq!(fee_token.balance(&acceptor), (10 * MUL_VAL - 62) as i128);
+    assert_eq!(token_swap.accrued_fees(&fee_token_id), 2500 + 2500 + 62);
+
+    // opting out restores the default
+    token_swap.pay_fees_in_fee_token(&acceptor, &false);
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 500);
+}
+
+
+#[test]
+fn test_fee_schedule() {
+    let e = Env::default();
+    e.mock_all_auths();
+    e.ledger().with_mut(|li| li.sequence_number = 100);
+
+    let TestSetup { token_swap, admin, offeror, acceptor, send_token, recv_token } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+    let old_fee = FeeInfo { maker_fee_rate: DEF_FEE_RATE, taker_fee_rate: DEF_FEE_RATE };
+    let new_fee = FeeInfo { maker_fee_rate: 10, taker_fee_rate: 50 };
+
+    // changes can't take effect before the minimum delay
+    assert_eq!(token_swap.try_set_fee(&admin, &10, &50, &(100 + MIN_FEE_DELAY - 1)), 
+        Err(Ok(SwapError::InvalidFeeSchedule)));
+    token_swap.set_fee(&admin, &10, &50, &(100 + MIN_FEE_DELAY));
+    let pending_fee = PendingFee { fee_info: new_fee.clone(), effective_ledger: 100 + MIN_FEE_DELAY };
+    assert_eq!(token_swap.get_fee(), (old_fee.clone(), Some(pending_fee)));
+
+    // the current rates apply until then
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+    assert_eq!(token_swap.load_offer(&offer_id).fee_escrow, 12500);
+
+    e.ledger().with_mut(|li| li.sequence_number = 100 + MIN_FEE_DELAY);
+    assert_eq!(token_swap.get_fee(), (new_fee.clone(), None));
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), 500);
+
+    // a later change records the applied one in the history
+    token_swap.set_fee(&admin, &DEF_FEE_RATE, &DEF_FEE_RATE, &(200 + 2 * MIN_FEE_DELAY));
+
+    // other fee settings are scheduled the same way, a new change replaces the pending one
+    token_swap.set_pair_fee(&admin, &send_token.address, &recv_token.address, &5, &5, &(150 + 2 * MIN_FEE_DELAY));
+    token_swap.set_pair_fee(&admin, &send_token.address, &recv_token.address, &10, &10, &(150 + 2 * MIN_FEE_DELAY));
+    let (token_a, token_b) = if send_token.address < recv_token.address {
+        (send_token.address.clone(), recv_token.address.clone())
+    }
+    else {
+        (recv_token.address.clone(), send_token.address.clone())
+    };
+    let pair_change = FeeChange::PairFee(token_a, token_b, PairFee { maker_fee_rate: 10, taker_fee_rate: 10 });
+    assert_eq!(token_swap.get_pending_fee_changes(), vec![&e, 
+        (200 + 2 * MIN_FEE_DELAY, FeeChange::Fee(old_fee.clone())), 
+        (150 + 2 * MIN_FEE_DELAY, pair_change.clone())]);
+    assert_eq!(token_swap.get_pair_fee(&send_token.address, &recv_token.address), None);
+
+    // the history is in ledger order, whatever order the changes were scheduled in
+    e.ledger().with_mut(|li| li.sequence_number = 200 + 2 * MIN_FEE_DELAY);
+    assert_eq!(token_swap.get_pending_fee_changes(), vec![&e]);
+    assert_eq!(token_swap.get_pair_fee(&send_token.address, &recv_token.address), Some((10, 10)));
+    let history = vec![&e, 
+        (100, FeeChange::Fee(old_fee.clone())), 
+        (100 + MIN_FEE_DELAY, FeeChange::Fee(new_fee.clone())), 
+        (150 + 2 * MIN_FEE_DELAY, pair_change), 
+        (200 + 2 * MIN_FEE_DELAY, FeeChange::Fee(old_fee.clone()))];
+    assert_eq!(token_swap.get_fee_history(&0, &u32::MAX), (history.clone(), None));
+    assert_eq!(token_swap.get_fee_history(&0, &0), (vec![&e], None));
+
+    // applying the changes keeps their positions
+    token_swap.set_fee(&admin, &10, &50, &(200 + 3 * MIN_FEE_DELAY));
+    assert_eq!(token_swap.get_fee_history(&0, &2), (history.slice(0..2), Some(2)));
+    assert_eq!(token_swap.get_fee_history(&2, &2), (history.slice(2..4), None));
+
+    // pending changes are limited per kind of setting, tier tables don't hold up prices
+    let tiers = vec![&e, FeeTier { volume: 0, fee_rate: 10 }];
+    for _ in 0..MAX_PENDING_FEE_CHANGES {
+        token_swap.set_fee_tiers(&admin, &Address::generate(&e), &tiers, &(200 + 3 * MIN_FEE_DELAY));
+    }
+    assert_eq!(token_swap.try_set_fee_tiers(&admin, &Address::generate(&e), &tiers, &(200 + 3 * MIN_FEE_DELAY)), 
+        Err(Ok(SwapError::InvalidFeeSchedule)));
+    token_swap.set_fee_token_price(&admin, &send_token.address, &(2 * u128::pow(10, 7)), &(200 + 3 * MIN_FEE_DELAY));
+    assert_eq!(token_swap.get_pending_fee_changes().len(), 2 + MAX_PENDING_FEE_CHANGES);
+}
+
+
+#[test]
+fn test_accept_protection() {
+    let e = Env::default();
+    e.mock_all_auths();
+    e.ledger().with_mut(|li| li.sequence_number = 100);
+
+    let TestSetup { token_swap, offeror, acceptor, send_token, recv_token, .. } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+
+    // the offeror raises the price before the fill lands
+    token_swap.update_offer(&offeror, &offer_id, &(100 * MUL_VAL), &(10 * MUL_VAL));
+    assert_eq!(token_swap.try_accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &(100 * MUL_VAL), &0), 
+        Err(Ok(SwapError::SendAmountBelowMin)));
+
+    // fills past the deadline are rejected
+    assert_eq!(token_swap.try_accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &(50 * MUL_VAL), &99), 
+        Err(Ok(SwapError::DeadlinePassed)));
+
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &(50 * MUL_VAL), &100);
+    assert_eq!(send_token.balance(&acceptor), (50 * MUL_VAL) as i128);
+}
+
+
+#[test]
+fn test_increase_decrease_offer() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, offeror, acceptor, send_token, recv_token, .. } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e], &FillPolicy::Partial);
+
+    // topping up keeps the price and escrows the maker fee for the extra amount
+    assert_eq!(token_swap.try_increase_offer(&acceptor, &offer_id, &(100 * MUL_VAL)), Err(Ok(SwapError::InvalidOfferor)));
+    assert_eq!(token_swap.try_increase_offer(&offeror, &offer_id, &0), Err(Ok(SwapError::ZeroAmount)));
+    token_swap.increase_offer(&offeror, &offer_id, &(100 * MUL_VAL));
+    let offer = token_swap.load_offer(&offer_id);
+    assert_eq!((offer.send_amount, offer.recv_amount, offer.fee_escrow), (600 * MUL_VAL, 60 * MUL_VAL, 15000));
+    assert_eq!(send_token.balance(&offeror), (400 * MUL_VAL) as i128 - 15000);
+
+    // withdrawing refunds the maker fee for the withdrawn amount
+    assert_eq!(token_swap.try_decrease_offer(&offeror, &offer_id, &(600 * MUL_VAL)), 
+        Err(Ok(SwapError::AmountAboveSendAmount)));
+    token_swap.decrease_offer(&offeror, &offer_id, &(300 * MUL_VAL));
+    let offer = token_swap.load_offer(&offer_id);
+    assert_eq!((offer.send_amount, offer.recv_amount, offer.min_recv_amount, offer.fee_escrow), (300 * MUL_VAL, 30 * MUL_VAL, 10 * MUL_VAL, 7500));
+    assert_eq!(send_token.balance(&offeror), (700 * MUL_VAL) as i128 - 7500);
+
+    // the offer keeps its id and fills at the same price
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &(100 * MUL_VAL), &0);
+    assert_eq!(send_token.balance(&acceptor), (100 * MUL_VAL) as i128);
+    assert_eq!(token_swap.accrued_fees(&send_token.address), 2500);
+}
+
+
+#[test]
+fn test_private_offer() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, offeror, acceptor, send_token, recv_token, .. } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+    let outsider = Address::generate(&e);
+    token::StellarAssetClient::new(&e, &recv_token.address).mint(&outsider, &((100 * MUL_VAL) as i128));
+
+    let mut too_many = Vec::new(&e);
+    for _ in 0..11 {
+        too_many.push_back(Address::generate(&e));
+    }
+    assert_eq!(token_swap.try_create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &too_many, &FillPolicy::Partial), Err(Ok(SwapError::TooManyAcceptors)));
+
+    // only the designated acceptor can fill
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(500 * MUL_VAL), &(50 * MUL_VAL), &(10 * MUL_VAL), &vec![&e, acceptor.clone()], &FillPolicy::Partial);
+    assert_eq!(token_swap.load_offer(&offer_id).acceptors, vec![&e, acceptor.clone()]);
+    assert_eq!(token_swap.try_accept_offer(&outsider, &offer_id, &(10 * MUL_VAL), &0, &0), 
+        Err(Ok(SwapError::InvalidAcceptor)));
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(send_token.balance(&acceptor), (100 * MUL_VAL) as i128);
+}
+
+
+#[test]
+fn test_fill_policy() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, offeror, acceptor, send_token, recv_token, .. } = setup_token_swap(&e);
+    const MUL_VAL: u64 = u64::pow(10, TOKEN_DECIMALS);
+
+    // all or none only takes a full fill
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(100 * MUL_VAL), &(10 * MUL_VAL), &MUL_VAL, &vec![&e], &FillPolicy::AllOrNone);
+    assert_eq!(token_swap.load_offer(&offer_id).fill_policy, FillPolicy::AllOrNone);
+    assert_eq!(token_swap.try_accept_offer(&acceptor, &offer_id, &(5 * MUL_VAL), &0, &0), 
+        Err(Ok(SwapError::FillNotAllowed)));
+    token_swap.accept_offer(&acceptor, &offer_id, &(10 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.load_offer(&offer_id).status, OfferStatus::COMPLETE);
+
+    // lots must divide the amounts
+    assert_eq!(token_swap.try_create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(100 * MUL_VAL), &(10 * MUL_VAL), &MUL_VAL, &vec![&e], &FillPolicy::MinLot(0)), 
+        Err(Ok(SwapError::InvalidFillPolicy)));
+    assert_eq!(token_swap.try_create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(100 * MUL_VAL), &(10 * MUL_VAL), &MUL_VAL, &vec![&e], &FillPolicy::MinLot(3 * MUL_VAL)), 
+        Err(Ok(SwapError::InvalidFillPolicy)));
+
+    // fills must be whole lots
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &(100 * MUL_VAL), &(10 * MUL_VAL), &(2 * MUL_VAL), &vec![&e], &FillPolicy::MinLot(2 * MUL_VAL));
+    assert_eq!(token_swap.try_accept_offer(&acceptor, &offer_id, &(3 * MUL_VAL), &0, &0), 
+        Err(Ok(SwapError::FillNotAllowed)));
+    token_swap.accept_offer(&acceptor, &offer_id, &(4 * MUL_VAL), &0, &0);
+    assert_eq!(token_swap.try_update_offer(&offeror, &offer_id, &(7 * MUL_VAL), &(2 * MUL_VAL)), 
+        Err(Ok(SwapError::InvalidFillPolicy)));
+    assert_eq!(token_swap.try_decrease_offer(&offeror, &offer_id, &(10 * MUL_VAL)), 
+        Err(Ok(SwapError::InvalidFillPolicy)));
+    token_swap.decrease_offer(&offeror, &offer_id, &(20 * MUL_VAL));
+    assert_eq!(token_swap.load_offer(&offer_id).recv_amount, 4 * MUL_VAL);
+}
+
+
+#[test]
+fn test_large_amounts() {
+    let e = Env::default();
+    e.mock_all_auths();
+
+    let TestSetup { token_swap, offeror, acceptor, send_token, recv_token, .. } = setup_token_swap(&e);
+    // 10,000 tokens with 7 decimals on each side
+    const AMOUNT: u64 = 10_000 * u64::pow(10, 7);
+    token::StellarAssetClient::new(&e, &send_token.address).mint(&offeror, &(2 * AMOUNT as i128));
+    token::StellarAssetClient::new(&e, &recv_token.address).mint(&acceptor, &(2 * AMOUNT as i128));
+
+    // sums that don't fit the amounts are rejected instead of overflowing
+    assert_eq!(token_swap.try_create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &u64::MAX, &AMOUNT, &AMOUNT, &vec![&e], &FillPolicy::Partial), Err(Ok(SwapError::AmountOverflow)));
+
+    let offer_id = token_swap.create_offer(&offeror, &send_token.address, &recv_token.address, 
+        &0, &AMOUNT, &AMOUNT, &AMOUNT, &vec![&e], &FillPolicy::Partial);
+    token_swap.accept_offer(&acceptor, &offer_id, &AMOUNT, &AMOUNT, &0);
+    assert_eq!(token_swap.load_offer(&offer_id).status, OfferStatus::COMPLETE);
+    assert_eq!(send_token.balance(&acceptor), AMOUNT as i128);
+    assert_eq!(recv_token.balance(&offeror), AMOUNT as i128);
+    assert_eq!(token_swap.accrued_fees(&send_token.address), (AMOUNT / 400) as u128);
+    assert_eq!(token_swap.accrued_fees(&recv_token.address), (AMOUNT / 400) as u128);
 }
@@ -0,0 +1,91 @@
+use soroban_sdk::{ Address, Env, Vec };
+
+use crate::storage_types::{ BALANCE_BUMP_AMOUNT, BALANCE_LIFETIME_THRESHOLD, MAX_FEE_TIERS,
+    DataKey, FeeChange, FeeTier, Role
+};
+use crate::error::SwapError;
+
+use crate::fee::{
+    fee_get, fee_cap_get, fee_rates, fee_change_due, fee_change_schedule,
+};
+use crate::role::{
+    require_role,
+};
+
+
+pub fn volume_get(e: &Env, account: &Address, token: &Address) -> u128 {
+    let key = DataKey::Volume(account.clone(), token.clone());
+    e.storage().persistent().get(&key).unwrap_or(0)
+}
+
+// Adds a filled amount to the account's traded volume of the token.
+pub fn volume_add(e: &Env, account: &Address, token: &Address, amount: u64) {
+    let key = DataKey::Volume(account.clone(), token.clone());
+    let volume = volume_get(e, account, token) + amount as u128;
+    e.storage().persistent().set(&key, &volume);
+    e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+}
+
+pub fn tiers_get(e: &Env, token: &Address) -> Vec<FeeTier> {
+    if let Some(FeeChange::Tiers(_, tiers)) = fee_change_due(e, &FeeChange::Tiers(token.clone(), Vec::new(e))) {
+        return tiers;
+    }
+
+    let key = DataKey::FeeTiers(token.clone());
+    e.storage().persistent().get(&key).unwrap_or(Vec::new(e))
+}
+
+// Schedules the tier table of the token from `effective_ledger` on, an empty table removes it.
+// At most MAX_FEE_TIERS tiers, sorted by strictly increasing volume.
+// Must be authorized by a fee manager.
+pub fn tiers_set(e: &Env, caller: &Address, token: &Address, tiers: &Vec<FeeTier>, effective_ledger: u32) -> Result<(), SwapError> {
+    require_role(e, Role::FeeManager, caller)?;
+
+    if tiers.len() > MAX_FEE_TIERS {
+        return Err(SwapError::InvalidFeeTiers);
+    }
+    let fee_cap = fee_cap_get(e);
+    let mut prev_volume: Option<u128> = None;
+    for tier in tiers.iter() {
+        if prev_volume.is_some_and(|volume| tier.volume <= volume) || tier.fee_rate > fee_cap {
+            return Err(SwapError::InvalidFeeTiers);
+        }
+        prev_volume = Some(tier.volume);
+    }
+
+    fee_change_schedule(e, &FeeChange::Tiers(token.clone(), tiers.clone()), effective_ledger)
+}
+
+pub fn tiers_write(e: &Env, token: &Address, tiers: &Vec<FeeTier>) {
+    let key = DataKey::FeeTiers(token.clone());
+    if tiers.is_empty() {
+        e.storage().persistent().remove(&key);
+    }
+    else {
+        e.storage().persistent().set(&key, tiers);
+        e.storage().persistent().extend_ttl(&key, BALANCE_LIFETIME_THRESHOLD, BALANCE_BUMP_AMOUNT);
+    }
+}
+
+// Discounted fee rate of the account for the token, never above `fee_rate`.
+pub fn tier_rate(e: &Env, account: &Address, token: &Address, fee_rate: u32) -> u32 {
+    let volume = volume_get(e, account, token);
+
+    let mut rate = fee_rate;
+    for tier in tiers_get(e, token).iter() {
+        if volume < tier.volume {
+            break;
+        }
+        rate = fee_rate.min(tier.fee_rate);
+    }
+    rate
+}
+
+// Rates the account pays on an offer of send_token for recv_token, pair override included:
+// as maker tiered on its send_token volume, as taker tiered on its recv_token volume.
+pub fn tier_fee_rates(e: &Env, account: &Address, send_token: &Address, recv_token: &Address) -> Result<(u32, u32), SwapError> {
+    let fee_info = fee_get(e)?;
+    let (maker_fee_rate, taker_fee_rate) = fee_rates(e, &fee_info, send_token, recv_token);
+    Ok((tier_rate(e, account, send_token, maker_fee_rate), 
+        tier_rate(e, account, recv_token, taker_fee_rate)))
+}
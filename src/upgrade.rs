@@ -0,0 +1,58 @@
+const UPGRADE: Symbol = symbol_short!("UPGRADE");
+
+use soroban_sdk::{ symbol_short, Address, BytesN, Env, Symbol };
+
+use crate::storage_types::{ CONTRACT_VERSION, DataKey, Role };
+use crate::error::SwapError;
+
+use crate::role::{
+    require_role,
+};
+
+
+// Version of the storage layout, 0 for deployments made before versioning.
+pub fn version_get(e: &Env) -> u32 {
+    e.storage().instance().get(&DataKey::Version).unwrap_or(0)
+}
+
+pub fn version_write(e: &Env, version: u32) {
+    e.storage().instance().set(&DataKey::Version, &version);
+}
+
+// Replaces the contract code, `contract_migrate` has to be called afterwards.
+// Must be authorized by an upgrader.
+pub fn contract_upgrade(e: &Env, caller: &Address, new_wasm_hash: &BytesN<32>) -> Result<(), SwapError> {
+    require_role(e, Role::Upgrader, caller)?;
+
+    e.deployer().update_current_contract_wasm(new_wasm_hash.clone());
+
+    // emit Upgraded event
+    e.events().publish((UPGRADE, symbol_short!("Upgraded")), 
+        (version_get(e), new_wasm_hash.clone())
+    );
+
+    Ok(())
+}
+
+// Converts the storage from the stored version to CONTRACT_VERSION, once per version bump.
+// Must be authorized by an upgrader.
+pub fn contract_migrate(e: &Env, caller: &Address) -> Result<(), SwapError> {
+    require_role(e, Role::Upgrader, caller)?;
+
+    let from_version = version_get(e);
+    if from_version >= CONTRACT_VERSION {
+        return Err(SwapError::AlreadyMigrated);
+    }
+
+    // Layout changes go here, one step per version, e.g.
+    // if from_version < 2 { ... }
+
+    version_write(e, CONTRACT_VERSION);
+
+    // emit Migrated event
+    e.events().publish((UPGRADE, symbol_short!("Migrated")), 
+        (from_version, CONTRACT_VERSION)
+    );
+
+    Ok(())
+}